@@ -1,21 +1,53 @@
+use std::path::PathBuf;
 use std::time::Duration;
 
+use chrono::Utc;
 use structopt::StructOpt;
 
 use crate::error::{Error, Result};
-use crate::model::Timestamp;
-use crate::utils::{parse::parse_duration, time::try_parse_time};
+use crate::input::ListenAddr;
+use crate::parse::TimestampSource;
+use crate::query::{CardinalityLimits, LabelLimit, OverflowPolicy};
+use crate::runner::RunnerOptions;
+use crate::utils::{
+    parse::parse_duration,
+    time::{parse_timezone, TimeExpr, TimeRange, Timezone},
+};
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "pq", about = "pq command line arguments")]
 pub struct CliOpt {
     pub program: String,
 
-    #[structopt(long = "since", short = "s", parse(try_from_str = parse_time))]
-    pub since: Option<Timestamp>,
+    /// Input file; stdin is read if omitted. Seekable time-sorted files
+    /// are bisected to quickly find the start of the --since range.
+    #[structopt(parse(from_os_str))]
+    pub input: Option<PathBuf>,
 
-    #[structopt(long = "until", short = "u", parse(try_from_str = parse_time))]
-    pub until: Option<Timestamp>,
+    /// Read lines from network clients instead of a file (tcp://, udp://, or unix:// address).
+    #[structopt(long = "listen", conflicts_with_all = &["input", "last"])]
+    pub listen: Option<ListenAddr>,
+
+    /// Absolute (2021-01-01T00:00:00Z) or relative (2h, now-30m, yesterday, today 09:00) time.
+    #[structopt(long = "since", short = "s")]
+    pub since: Option<TimeExpr>,
+
+    /// Absolute (2021-01-01T00:00:00Z) or relative (2h, now-30m, yesterday, today 09:00) time.
+    #[structopt(long = "until", short = "u")]
+    pub until: Option<TimeExpr>,
+
+    /// Time range relative to the last timestamp of the input file.
+    #[structopt(long = "last", short = "l", conflicts_with = "since", parse(try_from_str = parse_duration))]
+    pub last: Option<Duration>,
+
+    /// Timezone for relative time expressions and timestamps without a UTC offset
+    /// (UTC, +02:00, -0530, Europe/Berlin, etc).
+    #[structopt(long = "tz", parse(try_from_str = parse_timezone))]
+    pub tz: Option<Timezone>,
+
+    /// Year of timestamps lacking one, e.g. of BSD syslog (the current one by default).
+    #[structopt(long = "year")]
+    pub year: Option<i32>,
 
     #[structopt(long = "interval", short = "I", parse(try_from_str = parse_duration))]
     pub interval: Option<Duration>,
@@ -23,6 +55,51 @@ pub struct CliOpt {
     #[structopt(long = "lookback", short = "b", parse(try_from_str = parse_duration))]
     pub lookback: Option<Duration>,
 
+    /// Timestamps for records without a time field: arrival (wall-clock time),
+    /// line[*<step>] (line number on a virtual clock), or <start>+<step> (e.g. now+1s).
+    #[structopt(long = "timestamps")]
+    pub timestamps: Option<TimestampSource>,
+
+    /// Name of the series having a sample of value 1 for every record, i.e. counting events
+    /// (__events__ by default).
+    #[structopt(long = "events")]
+    pub events: Option<String>,
+
+    /// Max number of distinct values of any label (100) or a certain one (path=100).
+    /// Can be repeated.
+    #[structopt(long = "max-label-values", number_of_values = 1)]
+    pub max_label_values: Vec<LabelLimit>,
+
+    /// Max number of distinct series (label sets) queries deal with.
+    #[structopt(long = "max-series")]
+    pub max_series: Option<usize>,
+
+    /// What to do when a cardinality limit is exceeded: collapse the least frequent
+    /// label values into __other__, or fail.
+    #[structopt(long = "on-cardinality-overflow", default_value = "collapse")]
+    pub on_cardinality_overflow: OverflowPolicy,
+
+    /// How much out of timestamp order records can be and still get queried (e.g. 5s).
+    /// Records arriving later are dropped and counted.
+    #[structopt(long = "max-lateness", parse(try_from_str = parse_duration))]
+    pub max_lateness: Option<Duration>,
+
+    /// Number of threads decoding and mapping lines in parallel. Meant for big files;
+    /// with more than one job, lines are read in batches. Ignored by programs mining templates.
+    #[structopt(
+        long = "jobs",
+        short = "j",
+        default_value = "1",
+        conflicts_with = "listen",
+        parse(try_from_str = parse_jobs)
+    )]
+    pub jobs: usize,
+
+    /// File to keep input offsets in between runs. Reading resumes where the
+    /// previous run stopped, also if the input has been rotated (and gzipped) since.
+    #[structopt(long = "state-file", parse(from_os_str), conflicts_with = "listen")]
+    pub state_file: Option<PathBuf>,
+
     #[structopt(long = "i", short = "interactive")]
     pub interactive: bool,
 
@@ -30,9 +107,58 @@ pub struct CliOpt {
     pub verbose: bool,
 }
 
-fn parse_time(s: &str) -> Result<Timestamp> {
-    match try_parse_time(s) {
-        Some(t) => Ok(t),
-        None => Err(Error::new("couldn't guess time format")),
+impl CliOpt {
+    pub fn cardinality_limits(&self) -> CardinalityLimits {
+        CardinalityLimits {
+            labels: self.max_label_values.clone(),
+            max_series: self.max_series,
+            policy: self.on_cardinality_overflow,
+        }
+    }
+
+    /// Options of the run over the given time range (see time_range()).
+    pub fn runner_options(&self, range: TimeRange) -> RunnerOptions {
+        let mut options = RunnerOptions::new(
+            self.verbose,
+            self.interactive,
+            Some(range),
+            self.interval,
+            self.lookback,
+        )
+        .with_jobs(self.jobs)
+        .with_cardinality_limits(self.cardinality_limits());
+        if let Some(tz) = self.tz {
+            options = options.with_timezone(tz);
+        }
+        if let Some(year) = self.year {
+            options = options.with_year(year);
+        }
+        if let Some(max_lateness) = self.max_lateness {
+            options = options.with_max_lateness(max_lateness);
+        }
+        if let Some(timestamps) = self.timestamps {
+            options = options.with_timestamp_source(timestamps);
+        }
+        if let Some(ref events) = self.events {
+            options = options.with_event_series(events.clone());
+        }
+        options
+    }
+
+    /// Resolves --since and --until relative to the current time.
+    pub fn time_range(&self) -> Result<TimeRange> {
+        let now = Utc::now();
+        let tz = self.tz.unwrap_or_default();
+        TimeRange::new(
+            self.since.map(|t| t.resolve(now, &tz, self.year)),
+            self.until.map(|t| t.resolve(now, &tz, self.year)),
+        )
+    }
+}
+
+fn parse_jobs(s: &str) -> Result<usize> {
+    match s.parse() {
+        Ok(0) | Err(_) => Err(Error::new("number of jobs must be a positive integer")),
+        Ok(jobs) => Ok(jobs),
     }
 }
@@ -2,7 +2,7 @@ use std::{error, fmt};
 
 pub struct Error {
     message: String,
-    source: Option<Box<dyn error::Error>>,
+    source: Option<Box<dyn error::Error + Send + Sync>>,
 }
 
 impl Error {
@@ -60,7 +60,7 @@ impl From<&str> for Error {
     }
 }
 
-impl<E: error::Error + 'static> From<(String, E)> for Error {
+impl<E: error::Error + Send + Sync + 'static> From<(String, E)> for Error {
     fn from((message, err): (String, E)) -> Self {
         Self {
             message,
@@ -69,7 +69,7 @@ impl<E: error::Error + 'static> From<(String, E)> for Error {
     }
 }
 
-impl<E: error::Error + 'static> From<(&str, E)> for Error {
+impl<E: error::Error + Send + Sync + 'static> From<(&str, E)> for Error {
     fn from((message, err): (&str, E)) -> Self {
         Self {
             message: message.into(),
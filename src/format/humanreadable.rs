@@ -21,11 +21,17 @@ impl HumanReadableFormatter {
         }
     }
 
-    fn format_tuple_entry(&self, line_no: usize, data: &[String]) -> Result<Vec<u8>> {
+    fn format_tuple_entry(&self, line_no: usize, data: &[Option<String>]) -> Result<Vec<u8>> {
+        // Absent fields are printed as empty ones.
+        let data = data
+            .iter()
+            .map(|d| d.as_deref().unwrap_or_default())
+            .collect::<Vec<_>>()
+            .join("\t");
         if self.verbose {
-            Ok(format!("{}: {}", line_no, data.join("\t")).into_bytes())
+            Ok(format!("{}: {}", line_no, data).into_bytes())
         } else {
-            Ok(data.join("\t").into_bytes())
+            Ok(data.into_bytes())
         }
     }
 
@@ -57,6 +63,12 @@ impl HumanReadableFormatter {
                 ),
             );
         }
+        for bucket in record.buckets() {
+            parts.push(format!(
+                "{}{{le=\"{}\"}}={}",
+                bucket.name, bucket.le, bucket.count
+            ));
+        }
 
         if self.verbose {
             Ok(format!("{}: {}", record.line_no(), parts.join("\t")).into_bytes())
@@ -13,7 +13,7 @@ use crate::query::QueryValue;
 #[derive(Serialize)]
 struct TupleEntryRepr<'a> {
     line: usize,
-    data: &'a [String],
+    data: &'a [Option<String>],
 }
 
 #[derive(Serialize)]
@@ -30,6 +30,15 @@ struct RecordRepr<'a> {
     timestamp: Option<Timestamp>,
     labels: BTreeMap<&'a String, &'a String>,
     values: BTreeMap<&'a String, &'a f64>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    buckets: Vec<BucketRepr<'a>>,
+}
+
+#[derive(Serialize)]
+struct BucketRepr<'a> {
+    name: &'a String,
+    le: &'a String,
+    value: f64,
 }
 
 pub struct JSONFormatter {
@@ -45,7 +54,7 @@ impl JSONFormatter {
         }
     }
 
-    fn format_tuple_entry(&self, line: usize, data: &[String]) -> Result<Vec<u8>> {
+    fn format_tuple_entry(&self, line: usize, data: &[Option<String>]) -> Result<Vec<u8>> {
         if self.verbose {
             serde_json::to_vec(&TupleEntryRepr { line, data })
         } else {
@@ -72,6 +81,15 @@ impl JSONFormatter {
             timestamp: record.timestamp(),
             labels: record.labels().iter().collect(),
             values: record.values().iter().collect(),
+            buckets: record
+                .buckets()
+                .iter()
+                .map(|b| BucketRepr {
+                    name: &b.name,
+                    le: &b.le,
+                    value: b.count,
+                })
+                .collect(),
         };
 
         if self.verbose {
@@ -120,14 +120,9 @@ impl Scalar {
     }
 }
 
+#[derive(Default)]
 pub struct PromApiFormatter {}
 
-impl Default for PromApiFormatter {
-    fn default() -> Self {
-        Self {}
-    }
-}
-
 impl PromApiFormatter {
     // Instant query - instant vector
     // {
@@ -1,3 +1,9 @@
+mod net;
 mod reader;
+mod seek;
+mod state;
 
+pub use net::*;
 pub use reader::*;
+pub use seek::*;
+pub use state::*;
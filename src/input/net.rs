@@ -0,0 +1,308 @@
+use std::io::{self, BufRead, BufReader, Read};
+use std::net::{SocketAddr, TcpListener, UdpSocket};
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::thread;
+
+use super::reader::Line;
+use crate::error::{Error, Result};
+use crate::model::Labels;
+
+const MAX_DATAGRAM_SIZE: usize = 64 * 1024;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ListenAddr {
+    Tcp(String),
+    Udp(String),
+    Unix(PathBuf),
+}
+
+impl std::str::FromStr for ListenAddr {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some(addr) = s.strip_prefix("tcp://") {
+            return Ok(ListenAddr::Tcp(addr.to_owned()));
+        }
+        if let Some(addr) = s.strip_prefix("udp://") {
+            return Ok(ListenAddr::Udp(addr.to_owned()));
+        }
+        if let Some(path) = s.strip_prefix("unix://") {
+            return Ok(ListenAddr::Unix(PathBuf::from(path)));
+        }
+        Err(Error::new(
+            "listen address must be tcp://<host>:<port>, udp://<host>:<port>, or unix://<path>",
+        ))
+    }
+}
+
+type Message = io::Result<(Vec<u8>, Labels)>;
+
+/// Reads lines from network clients. Every TCP (or Unix socket) connection
+/// and every UDP datagram is split on lines, and the lines of all the
+/// clients are interleaved in the order of arrival. Lines are labeled
+/// with the protocol and the peer address.
+pub struct NetReader {
+    messages: mpsc::Receiver<Message>,
+    local_addr: Option<SocketAddr>,
+    /// Unix socket file to remove when done.
+    socket_path: Option<PathBuf>,
+    line_no: usize,
+}
+
+impl NetReader {
+    pub fn bind(addr: &ListenAddr) -> Result<Self> {
+        let (tx, rx) = mpsc::channel();
+
+        let mut socket_path = None;
+        let local_addr = match addr {
+            ListenAddr::Tcp(addr) => {
+                let listener =
+                    TcpListener::bind(addr).map_err(|e| ("couldn't bind TCP listener", e))?;
+                let local_addr = listener.local_addr().ok();
+                thread::spawn(move || {
+                    for stream in listener.incoming() {
+                        match stream {
+                            Ok(stream) => {
+                                let labels = peer_labels("tcp", stream.peer_addr().ok());
+                                let tx = tx.clone();
+                                thread::spawn(move || read_stream(stream, labels, tx));
+                            }
+                            Err(e) => {
+                                if tx.send(Err(e)).is_err() {
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                });
+                local_addr
+            }
+            ListenAddr::Udp(addr) => {
+                let socket = UdpSocket::bind(addr).map_err(|e| ("couldn't bind UDP socket", e))?;
+                let local_addr = socket.local_addr().ok();
+                thread::spawn(move || read_datagrams(socket, tx));
+                local_addr
+            }
+            ListenAddr::Unix(path) => {
+                bind_unix(path, tx)?;
+                socket_path = Some(path.clone());
+                None
+            }
+        };
+
+        Ok(Self {
+            messages: rx,
+            local_addr,
+            socket_path,
+            line_no: 0,
+        })
+    }
+
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addr
+    }
+}
+
+impl std::iter::Iterator for NetReader {
+    type Item = Result<Line>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.messages.recv() {
+            Ok(Ok((line, labels))) => {
+                self.line_no += 1;
+                Some(Ok((self.line_no, line, labels)))
+            }
+            Ok(Err(e)) => Some(Err(("network listener failed", e).into())),
+            Err(_) => None, // all listeners are gone
+        }
+    }
+}
+
+impl Drop for NetReader {
+    fn drop(&mut self) {
+        if let Some(ref path) = self.socket_path {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn bind_unix(path: &std::path::Path, tx: mpsc::Sender<Message>) -> Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::net::{UnixListener, UnixStream};
+
+    // A socket file left by a previous run (e.g. one killed by Ctrl-C, so
+    // Drop didn't run) would make the bind fail. Live sockets and anything
+    // else at the path are not ours to remove.
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if metadata.file_type().is_socket() {
+            if UnixStream::connect(path).is_ok() {
+                return Err(Error::from(format!(
+                    "Unix socket {} is in use by another listener",
+                    path.display()
+                )));
+            }
+            std::fs::remove_file(path).map_err(|e| ("couldn't remove stale Unix socket", e))?;
+        }
+    }
+
+    let listener = UnixListener::bind(path).map_err(|e| ("couldn't bind Unix socket", e))?;
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let mut labels = Labels::new();
+                    labels.insert("proto".to_owned(), "unix".to_owned());
+                    if let Some(path) = stream
+                        .peer_addr()
+                        .ok()
+                        .and_then(|a| a.as_pathname().map(|p| p.display().to_string()))
+                    {
+                        labels.insert("peer".to_owned(), path);
+                    }
+                    let tx = tx.clone();
+                    thread::spawn(move || read_stream(stream, labels, tx));
+                }
+                Err(e) => {
+                    if tx.send(Err(e)).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn bind_unix(_path: &std::path::Path, _tx: mpsc::Sender<Message>) -> Result<()> {
+    Err(Error::new(
+        "Unix sockets are not supported on this platform",
+    ))
+}
+
+fn peer_labels(proto: &str, peer: Option<SocketAddr>) -> Labels {
+    let mut labels = Labels::new();
+    labels.insert("proto".to_owned(), proto.to_owned());
+    if let Some(peer) = peer {
+        labels.insert("peer".to_owned(), peer.to_string());
+    }
+    labels
+}
+
+fn read_stream<S: Read>(stream: S, labels: Labels, tx: mpsc::Sender<Message>) {
+    let mut reader = BufReader::new(stream);
+    loop {
+        let mut buf = Vec::new();
+        match reader.read_until(b'\n', &mut buf) {
+            Ok(0) => break, // client disconnected
+            Ok(_) => {
+                if tx.send(Ok((buf, labels.clone()))).is_err() {
+                    break;
+                }
+            }
+            Err(e) => {
+                let _ = tx.send(Err(e));
+                break;
+            }
+        }
+    }
+}
+
+fn read_datagrams(socket: UdpSocket, tx: mpsc::Sender<Message>) {
+    let mut buf = vec![0; MAX_DATAGRAM_SIZE];
+    loop {
+        let alive = match socket.recv_from(&mut buf) {
+            Ok((len, peer)) => {
+                let labels = peer_labels("udp", Some(peer));
+                // A datagram usually carries a single message (e.g. syslog)
+                // but can also be a batch of newline-separated lines.
+                buf[..len]
+                    .split(|c| *c == b'\n')
+                    .filter(|line| !line.is_empty())
+                    .all(|line| tx.send(Ok((line.to_vec(), labels.clone()))).is_ok())
+            }
+            Err(e) => tx.send(Err(e)).is_ok(),
+        };
+        if !alive {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::net::TcpStream;
+
+    use super::*;
+
+    #[test]
+    fn test_tcp_and_udp() -> std::result::Result<(), Box<dyn std::error::Error>> {
+        let mut reader = NetReader::bind(&"tcp://127.0.0.1:0".parse()?)?;
+        let mut client = TcpStream::connect(reader.local_addr().unwrap())?;
+        client.write_all(b"foo\nbar\n")?;
+
+        let (line_no, line, _) = reader.next().unwrap()?;
+        assert_eq!((1, b"foo\n".to_vec()), (line_no, line));
+        let (line_no, line, labels) = reader.next().unwrap()?;
+        assert_eq!((2, b"bar\n".to_vec()), (line_no, line));
+        assert_eq!(Some("tcp"), labels.get("proto").map(String::as_str));
+        assert_eq!(
+            Some(client.local_addr()?.to_string()),
+            labels.get("peer").cloned()
+        );
+
+        let mut reader = NetReader::bind(&"udp://127.0.0.1:0".parse()?)?;
+        let client = UdpSocket::bind("127.0.0.1:0")?;
+        client.send_to(
+            b"<13>Jun  1 12:00:00 host app: hello",
+            reader.local_addr().unwrap(),
+        )?;
+
+        let (line_no, line, labels) = reader.next().unwrap()?;
+        assert_eq!(
+            (1, b"<13>Jun  1 12:00:00 host app: hello".to_vec()),
+            (line_no, line)
+        );
+        assert_eq!(Some("udp"), labels.get("proto").map(String::as_str));
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unix_socket_cleanup() -> std::result::Result<(), Box<dyn std::error::Error>> {
+        let path = std::env::temp_dir().join(format!("pq-test-{}.sock", std::process::id()));
+        let addr = ListenAddr::Unix(path.clone());
+
+        let reader = NetReader::bind(&addr)?;
+        assert!(path.exists());
+        // A live socket is left alone.
+        assert!(NetReader::bind(&addr).is_err());
+        assert!(path.exists());
+        drop(reader);
+        assert!(!path.exists());
+
+        // A stale socket file (e.g. after a crash) doesn't prevent binding.
+        let stale = std::os::unix::net::UnixListener::bind(&path)?;
+        drop(stale);
+        assert!(path.exists());
+        drop(NetReader::bind(&addr)?);
+        assert!(!path.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_listen_addr() {
+        assert_eq!(
+            Ok(ListenAddr::Tcp("127.0.0.1:5140".to_owned())),
+            "tcp://127.0.0.1:5140".parse().map_err(String::from)
+        );
+        assert_eq!(
+            Ok(ListenAddr::Unix(PathBuf::from("/tmp/pq.sock"))),
+            "unix:///tmp/pq.sock".parse().map_err(String::from)
+        );
+        assert!("http://127.0.0.1".parse::<ListenAddr>().is_err());
+    }
+}
@@ -1,6 +1,12 @@
 use std::io::BufRead;
 
 use crate::error::Result;
+use crate::model::Labels;
+
+/// Line number, line bytes (including the delimiter, if any), and labels
+/// describing where the line came from (e.g. the peer address of a network
+/// client). Line numbers start from 1.
+pub type Line = (usize, Vec<u8>, Labels);
 
 pub struct LineReader<R> {
     inner: R,
@@ -23,7 +29,7 @@ impl<R: BufRead> LineReader<R> {
 }
 
 impl<R: BufRead> std::iter::Iterator for LineReader<R> {
-    type Item = Result<(usize, Vec<u8>)>;
+    type Item = Result<Line>;
 
     fn next(&mut self) -> Option<Self::Item> {
         let mut buf = Vec::new();
@@ -31,7 +37,7 @@ impl<R: BufRead> std::iter::Iterator for LineReader<R> {
 
         match self.inner.read_until(self.delim, &mut buf) {
             Ok(0) => None,
-            Ok(_) => Some(Ok((self.line_no, buf))),
+            Ok(_) => Some(Ok((self.line_no, buf, Labels::new()))),
             Err(e) => Some(Err(("input reader failed", e).into())),
         }
     }
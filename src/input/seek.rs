@@ -0,0 +1,215 @@
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+
+use crate::error::Result;
+use crate::model::Timestamp;
+
+// Below this distance, bisecting further is more expensive than just
+// streaming the remaining lines through the decoder.
+const MIN_SEEK_STEP: u64 = 64 * 1024;
+
+/// Moves a seekable and time-sorted input close to (but never past) the
+/// first line with a timestamp not earlier than `since`.
+///
+/// The search bisects byte offsets. After every jump, the reader resyncs
+/// to the beginning of the next full line and probes the following lines
+/// until one of them yields a timestamp. Lines that `timestamp_of` can't
+/// handle (e.g. decoding or mapping errors) are skipped while probing.
+///
+/// Returns the byte offset the input has been positioned at. It's always
+/// the beginning of a line.
+pub fn seek_since<R, F>(inner: &mut R, since: Timestamp, timestamp_of: F) -> Result<u64>
+where
+    R: Read + Seek,
+    F: Fn(&[u8]) -> Option<Timestamp>,
+{
+    let size = inner
+        .seek(SeekFrom::End(0))
+        .map_err(|e| ("input seek failed", e))?;
+
+    let mut lo = 0;
+    let mut hi = size;
+    while hi - lo > MIN_SEEK_STEP {
+        let mid = lo + (hi - lo) / 2;
+        match probe(inner, mid, hi, &timestamp_of)? {
+            Some(ts) if ts < since => lo = mid,
+            _ => hi = mid,
+        }
+    }
+
+    resync(inner, lo)
+}
+
+/// Finds the timestamp of the last line that has one by reading a
+/// seekable input backwards. The input position is left undefined.
+pub fn last_timestamp<R, F>(inner: &mut R, timestamp_of: F) -> Result<Option<Timestamp>>
+where
+    R: Read + Seek,
+    F: Fn(&[u8]) -> Option<Timestamp>,
+{
+    let mut end = inner
+        .seek(SeekFrom::End(0))
+        .map_err(|e| ("input seek failed", e))?;
+
+    // Bytes of the (maybe partial) line at the beginning of the previously read chunk.
+    let mut carry = Vec::new();
+    while end > 0 {
+        let start = end.saturating_sub(MIN_SEEK_STEP);
+        let mut chunk = vec![0; (end - start) as usize];
+        inner
+            .seek(SeekFrom::Start(start))
+            .map_err(|e| ("input seek failed", e))?;
+        inner
+            .read_exact(&mut chunk)
+            .map_err(|e| ("input reader failed", e))?;
+        chunk.extend_from_slice(&carry);
+
+        let mut lines = chunk.split(|c| *c == b'\n').rev().peekable();
+        while let Some(line) = lines.next() {
+            // The very first line of the chunk may continue in the previous chunk.
+            if lines.peek().is_none() && start > 0 {
+                carry = line.to_vec();
+                break;
+            }
+            if let Some(ts) = timestamp_of(line) {
+                return Ok(Some(ts));
+            }
+        }
+        end = start;
+    }
+
+    Ok(None)
+}
+
+/// Returns the timestamp of the first line starting in [from, until)
+/// that has one.
+fn probe<R, F>(inner: &mut R, from: u64, until: u64, timestamp_of: &F) -> Result<Option<Timestamp>>
+where
+    R: Read + Seek,
+    F: Fn(&[u8]) -> Option<Timestamp>,
+{
+    let mut offset = resync(inner, from)?;
+    let mut reader = BufReader::new(&mut *inner);
+    let mut line = Vec::new();
+
+    while offset < until {
+        line.clear();
+        let len = reader
+            .read_until(b'\n', &mut line)
+            .map_err(|e| ("input reader failed", e))?;
+        if len == 0 {
+            break; // EOF
+        }
+
+        if let Some(ts) = timestamp_of(&line) {
+            return Ok(Some(ts));
+        }
+        offset += len as u64;
+    }
+
+    Ok(None)
+}
+
+/// Finds the offset of the first line starting at or after `offset`
+/// and positions the input there.
+fn resync<R: Read + Seek>(inner: &mut R, offset: u64) -> Result<u64> {
+    if offset == 0 {
+        inner
+            .seek(SeekFrom::Start(0))
+            .map_err(|e| ("input seek failed", e))?;
+        return Ok(0);
+    }
+
+    // Starting one byte earlier makes an offset that already points
+    // to the beginning of a line resync to itself.
+    inner
+        .seek(SeekFrom::Start(offset - 1))
+        .map_err(|e| ("input seek failed", e))?;
+
+    let mut skipped = Vec::new();
+    let len = BufReader::new(&mut *inner)
+        .read_until(b'\n', &mut skipped)
+        .map_err(|e| ("input reader failed", e))?;
+
+    // The buffered reader has most likely read past the line end.
+    let offset = offset - 1 + len as u64;
+    inner
+        .seek(SeekFrom::Start(offset))
+        .map_err(|e| ("input seek failed", e))?;
+    Ok(offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn timestamp_of(line: &[u8]) -> Option<Timestamp> {
+        std::str::from_utf8(line).ok()?.trim().parse().ok()
+    }
+
+    #[test]
+    fn test_seek_since() -> std::result::Result<(), Box<dyn std::error::Error>> {
+        // Every timestamp repeats twice and every 10th line is garbage.
+        let mut lines = Vec::new();
+        for i in 0..100_000 {
+            if i % 10 == 0 {
+                lines.push("garbage".to_owned());
+            } else {
+                lines.push(format!("{}", i / 2));
+            }
+        }
+        let data = lines.join("\n").into_bytes();
+
+        for since in &[-1, 0, 1, 17, 1000, 12345, 49999, 50000, 60000] {
+            let mut input = Cursor::new(data.clone());
+            let offset = seek_since(&mut input, *since, timestamp_of)? as usize;
+
+            let mut rest = Vec::new();
+            input.read_to_end(&mut rest)?;
+            assert_eq!(&data[offset..], &rest[..]);
+
+            // The first line at or after `since` must not be skipped...
+            let expected = data
+                .split(|c| *c == b'\n')
+                .scan(0, |pos, line| {
+                    let line_pos = *pos;
+                    *pos += line.len() + 1;
+                    Some((line_pos, timestamp_of(line)))
+                })
+                .find(|(_, ts)| ts.is_some_and(|ts| ts >= *since))
+                .map_or(data.len(), |(pos, _)| pos);
+            assert!(
+                offset <= expected,
+                "skipped too much while seeking {}",
+                since
+            );
+
+            // ...but the seek should land close to it (give or take a few lines).
+            assert!(
+                expected - offset <= MIN_SEEK_STEP as usize + 64,
+                "skipped too little while seeking {}",
+                since
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_last_timestamp() -> std::result::Result<(), Box<dyn std::error::Error>> {
+        let mut lines: Vec<String> = (0..100_000).map(|i| format!("{}", i)).collect();
+        lines.push("garbage".to_owned());
+        lines.push("".to_owned());
+        let data = lines.join("\n").into_bytes();
+
+        assert_eq!(
+            Some(99_999),
+            last_timestamp(&mut Cursor::new(data), timestamp_of)?
+        );
+        assert_eq!(
+            None,
+            last_timestamp(&mut Cursor::new(b"foo\nbar".to_vec()), timestamp_of)?
+        );
+        Ok(())
+    }
+}
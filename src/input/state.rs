@@ -0,0 +1,304 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use flate2::bufread::MultiGzDecoder;
+use serde::{Deserialize, Serialize};
+
+use super::reader::Line;
+use crate::error::{Error, Result};
+use crate::utils::hash::fnv1a;
+
+// Long enough to tell files apart, short enough to not hash huge lines.
+const FINGERPRINT_LEN: usize = 1024;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Checkpoints of previously processed inputs. Stored as JSON between runs
+/// to let pq resume reading growing files where it stopped last time.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct State {
+    inputs: BTreeMap<PathBuf, Checkpoint>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// Offset right after the last complete line read. For compressed
+    /// inputs, it's an offset in the decompressed stream.
+    pub offset: u64,
+    pub inode: u64,
+    /// Hash of the first line. Tells a rotated (or truncated) file apart
+    /// from the file previously living at the same path.
+    pub fingerprint: String,
+}
+
+impl State {
+    /// Loads the state, or returns an empty one if the file doesn't exist yet.
+    pub fn load(path: &Path) -> Result<Self> {
+        match fs::read(path) {
+            Ok(data) => {
+                serde_json::from_slice(&data).map_err(|e| ("malformed state file", e).into())
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(("couldn't read state file", e).into()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let data = serde_json::to_vec_pretty(self).map_err(|e| ("JSON serialization failed", e))?;
+
+        // Write-then-rename to never leave a half-written state behind.
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        fs::write(&tmp_path, data).map_err(|e| ("couldn't write state file", e))?;
+        fs::rename(&tmp_path, path).map_err(|e| ("couldn't write state file", e))?;
+        Ok(())
+    }
+
+    /// Finds the offset to resume reading the input from. The input is
+    /// first looked up by its path, then among all other inputs, since it
+    /// might be a rotated (renamed, maybe compressed) copy of a file
+    /// processed before. Compressed copies never share the inode with the
+    /// original file, so only their fingerprint is compared.
+    pub fn resume_offset(
+        &self,
+        input: &Path,
+        inode: u64,
+        fingerprint: &str,
+        compressed: bool,
+    ) -> u64 {
+        let matches =
+            |c: &&Checkpoint| c.fingerprint == fingerprint && (compressed || c.inode == inode);
+
+        self.inputs
+            .get(input)
+            .filter(matches)
+            .or_else(|| self.inputs.values().find(matches))
+            .map_or(0, |c| c.offset)
+    }
+
+    pub fn update(&mut self, input: PathBuf, checkpoint: Checkpoint) {
+        self.inputs.insert(input, checkpoint);
+    }
+}
+
+pub fn fingerprint(first_line: &[u8]) -> String {
+    let line = first_line.strip_suffix(b"\n").unwrap_or(first_line);
+    format!("{:016x}", fnv1a(&line[..line.len().min(FINGERPRINT_LEN)]))
+}
+
+#[cfg(unix)]
+pub fn inode(file: &fs::File) -> Result<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Ok(file
+        .metadata()
+        .map_err(|e| ("couldn't stat input", e))?
+        .ino())
+}
+
+#[cfg(not(unix))]
+pub fn inode(_file: &fs::File) -> Result<u64> {
+    Ok(0)
+}
+
+/// Peeks at the magic bytes of the input without consuming them.
+pub fn is_gzip<R: BufRead>(reader: &mut R) -> Result<bool> {
+    let buf = reader.fill_buf().map_err(|e| ("input reader failed", e))?;
+    Ok(buf.starts_with(&GZIP_MAGIC))
+}
+
+/// Fingerprints the first line of the (decompressed) file.
+pub fn fingerprint_file(path: &Path, compressed: bool) -> Result<String> {
+    let file = fs::File::open(path).map_err(|e| ("couldn't open input", e))?;
+    let mut reader: Box<dyn BufRead> = if compressed {
+        Box::new(BufReader::new(MultiGzDecoder::new(BufReader::new(file))))
+    } else {
+        Box::new(BufReader::new(file))
+    };
+
+    let mut line = Vec::new();
+    reader
+        .by_ref()
+        .take(FINGERPRINT_LEN as u64)
+        .read_until(b'\n', &mut line)
+        .map_err(|e| ("input reader failed", e))?;
+    Ok(fingerprint(&line))
+}
+
+/// Skips `count` bytes of a non-seekable input.
+pub fn skip<R: BufRead>(reader: &mut R, count: u64) -> Result<()> {
+    let skipped = io::copy(&mut reader.take(count), &mut io::sink())
+        .map_err(|e| ("input reader failed", e))?;
+    if skipped < count {
+        return Err(Error::new("input is shorter than the stored offset"));
+    }
+    Ok(())
+}
+
+/// Number of input lines the pipeline is done with, counted from where
+/// the reading started. Lines read ahead (e.g. by the --jobs workers) or
+/// left behind by --until don't count.
+#[derive(Clone, Debug, Default)]
+pub struct Progress(Arc<AtomicUsize>);
+
+impl Progress {
+    /// Marks the next line as processed.
+    pub fn advance(&self) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn lines(&self) -> usize {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Passes lines through while keeping track of the input offset right
+/// after the last complete (i.e. newline-terminated) line processed.
+pub struct OffsetTracker<I> {
+    inner: I,
+    /// Offset right after the last complete line read.
+    read: u64,
+    offsets: Arc<Mutex<Offsets>>,
+    progress: Progress,
+}
+
+struct Offsets {
+    processed: u64,
+    /// Line number -> offset right after it, for the lines read but not processed yet.
+    pending: VecDeque<(usize, u64)>,
+}
+
+impl Offsets {
+    fn acknowledge(&mut self, lines: usize) {
+        while let Some(&(line_no, offset)) = self.pending.front() {
+            if line_no > lines {
+                break;
+            }
+            self.processed = offset;
+            self.pending.pop_front();
+        }
+    }
+}
+
+impl<I> OffsetTracker<I> {
+    pub fn new(inner: I, start_at: u64, progress: Progress) -> Self {
+        Self {
+            inner,
+            read: start_at,
+            offsets: Arc::new(Mutex::new(Offsets {
+                processed: start_at,
+                pending: VecDeque::new(),
+            })),
+            progress,
+        }
+    }
+
+    pub fn offset(&self) -> ProcessedOffset {
+        ProcessedOffset {
+            offsets: Arc::clone(&self.offsets),
+            progress: self.progress.clone(),
+        }
+    }
+}
+
+impl<I: Iterator<Item = Result<Line>>> std::iter::Iterator for OffsetTracker<I> {
+    type Item = Result<Line>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = self.inner.next();
+        if let Some(Ok((line_no, ref line, _))) = item {
+            if line.ends_with(b"\n") {
+                self.read += line.len() as u64;
+                let mut offsets = self.offsets.lock().unwrap();
+                offsets.acknowledge(self.progress.lines());
+                offsets.pending.push_back((line_no, self.read));
+            }
+        }
+        item
+    }
+}
+
+/// Offset right after the last complete line processed, as seen by OffsetTracker.
+#[derive(Clone)]
+pub struct ProcessedOffset {
+    offsets: Arc<Mutex<Offsets>>,
+    progress: Progress,
+}
+
+impl ProcessedOffset {
+    pub fn get(&self) -> u64 {
+        let mut offsets = self.offsets.lock().unwrap();
+        offsets.acknowledge(self.progress.lines());
+        offsets.processed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_offset_tracker() {
+        let lines = ["foo\n", "barbaz\n", "qux"];
+        let progress = Progress::default();
+        let mut tracker = OffsetTracker::new(
+            lines
+                .iter()
+                .enumerate()
+                .map(|(i, l)| Ok((i + 1, l.as_bytes().to_vec(), Default::default()))),
+            10,
+            progress.clone(),
+        );
+        let offset = tracker.offset();
+
+        // Lines read ahead don't move the offset until processed.
+        tracker.next();
+        tracker.next();
+        assert_eq!(10, offset.get());
+        progress.advance();
+        assert_eq!(14, offset.get());
+        progress.advance();
+        assert_eq!(21, offset.get());
+
+        // Incomplete lines never do.
+        tracker.next();
+        progress.advance();
+        assert_eq!(21, offset.get());
+    }
+
+    #[test]
+    fn test_resume_offset() {
+        let mut state = State::default();
+        state.update(
+            PathBuf::from("/var/log/access.log"),
+            Checkpoint {
+                offset: 42,
+                inode: 1,
+                fingerprint: fingerprint(b"foo\n"),
+            },
+        );
+
+        let fp = fingerprint(b"foo");
+        #[rustfmt::skip]
+        let tests = [
+            ("/var/log/access.log", 1, fp.as_str(), false, 42),
+            ("/var/log/access.log", 2, fp.as_str(), false, 0),          // recreated
+            ("/var/log/access.log", 1, "0000000000000000", false, 0),   // truncated
+            ("/var/log/access.log.1", 1, fp.as_str(), false, 42),       // renamed
+            ("/var/log/access.log.1.gz", 3, fp.as_str(), true, 42),     // compressed
+            ("/var/log/other.log", 4, fp.as_str(), false, 0),
+        ];
+
+        for (input, inode, fingerprint, compressed, expected) in &tests {
+            assert_eq!(
+                *expected,
+                state.resume_offset(Path::new(input), *inode, fingerprint, *compressed),
+                "while resuming {}",
+                input
+            );
+        }
+    }
+}
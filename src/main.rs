@@ -1,11 +1,19 @@
-use std::io::{self, BufReader};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom};
+
+use flate2::bufread::MultiGzDecoder;
 
 use structopt::StructOpt;
 
 use pq::cliopt::CliOpt;
-use pq::input::LineReader;
+use pq::error::Error;
+use pq::input::{
+    fingerprint_file, inode, is_gzip, last_timestamp, seek_since, skip, Checkpoint, LineReader,
+    NetReader, OffsetTracker, Progress, State,
+};
+use pq::model::TimestampTrait;
 use pq::output::LineWriter;
-use pq::runner::{Runner, RunnerOptions};
+use pq::runner::{timestamp_probe, Runner};
 use pq::utils::time::TimeRange;
 
 //    'json'                                 // as is
@@ -34,20 +42,113 @@ use pq::utils::time::TimeRange;
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let opt = CliOpt::from_args();
 
+    let mut range = opt.time_range()?;
+    let mut checkpoint = None;
+    let mut processed = None;
+    let progress = Progress::default();
+
+    let reader: Box<dyn Iterator<Item = _> + Send> = match (&opt.listen, &opt.input) {
+        (Some(addr), _) => Box::new(NetReader::bind(addr)?),
+        (None, Some(path)) => {
+            let mut file = BufReader::new(File::open(path)?);
+            let compressed = is_gzip(&mut file)?;
+            let seekable = !compressed && file.get_ref().metadata()?.is_file();
+            let probe = timestamp_probe(&opt.program, opt.tz, opt.year)?;
+
+            if let Some(last) = opt.last {
+                let probe = match (&probe, seekable) {
+                    (Some(probe), true) => probe,
+                    (None, _) => {
+                        return Err(Error::new("--last requires a mapped timestamp field").into())
+                    }
+                    (_, false) => {
+                        return Err(Error::new("--last requires a seekable input file").into())
+                    }
+                };
+                let end = last_timestamp(&mut file, |line| probe.timestamp(line))?
+                    .ok_or_else(|| Error::new("--last couldn't find any timestamp in the input"))?;
+                // Same as range vectors, i.e. (end - last, end].
+                range = TimeRange::new(Some(end.sub(last) + 1), range.end())?;
+            }
+
+            let mut offset = match (range.start(), &probe, seekable) {
+                (Some(since), Some(probe), true) => {
+                    seek_since(&mut file, since, |line| probe.timestamp(line))?
+                }
+                _ => 0,
+            };
+
+            if let Some(state_file) = &opt.state_file {
+                let state = State::load(state_file)?;
+                let input = fs::canonicalize(path)?;
+                let inode = inode(file.get_ref())?;
+                let fingerprint = fingerprint_file(path, compressed)?;
+
+                let mut resume = state.resume_offset(&input, inode, &fingerprint, compressed);
+                if seekable && resume > file.get_ref().metadata()?.len() {
+                    resume = 0; // truncated in place
+                }
+                // Whatever is further, the stored offset or the --since position.
+                offset = offset.max(resume);
+                checkpoint = Some((state, input, inode, fingerprint));
+            }
+
+            // Line numbers are counted from the seek position.
+            let input: Box<dyn BufRead + Send> = if compressed {
+                let mut input = BufReader::new(MultiGzDecoder::new(file));
+                skip(&mut input, offset)?;
+                Box::new(input)
+            } else {
+                if seekable {
+                    file.seek(SeekFrom::Start(offset))?;
+                } else {
+                    skip(&mut file, offset)?;
+                }
+                Box::new(file)
+            };
+
+            let tracker = OffsetTracker::new(LineReader::new(input), offset, progress.clone());
+            processed = Some(tracker.offset());
+            Box::new(tracker)
+        }
+        (None, None) => {
+            if opt.last.is_some() {
+                return Err(Error::new("--last requires a seekable input file").into());
+            }
+            if opt.state_file.is_some() {
+                return Err(Error::new("--state-file requires an input file").into());
+            }
+            Box::new(LineReader::new(BufReader::new(io::stdin())))
+        }
+    };
+
+    let mut options = opt.runner_options(range);
+    if processed.is_some() {
+        options = options.with_progress(progress);
+    }
+
     let mut runner = Runner::new(
         &opt.program,
-        Box::new(LineReader::new(BufReader::new(io::stdin()))),
+        reader,
         Box::new(LineWriter::new(io::stdout())),
-        RunnerOptions::new(
-            opt.verbose,
-            opt.interactive,
-            Some(TimeRange::new(opt.since, opt.until)?),
-            opt.interval,
-            opt.lookback,
-        ),
+        options,
     )?;
 
     runner.run()?;
 
+    if let (Some(state_file), Some((mut state, input, inode, fingerprint)), Some(processed)) =
+        (&opt.state_file, checkpoint, processed)
+    {
+        state.update(
+            input,
+            Checkpoint {
+                offset: processed.get(),
+                inode,
+                fingerprint,
+            },
+        );
+        state.save(state_file)?;
+    }
+
     Ok(())
 }
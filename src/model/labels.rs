@@ -3,6 +3,7 @@ use std::collections::{BTreeSet, HashMap, HashSet};
 use regex::Regex;
 
 use crate::error::{Error, Result};
+use crate::utils::net::{parse_ip, Cidr};
 
 const NAME_LABEL: &str = "__name__";
 
@@ -52,7 +53,7 @@ impl LabelsTrait for Labels {
         let sorted: BTreeSet<_> = self.clone().into_iter().collect();
         sorted
             .into_iter()
-            .flat_map(|(name, value)| [name.as_bytes(), &[b'\xFF'], value.as_bytes()].concat())
+            .flat_map(|(name, value)| [name.as_bytes(), b"\xFF", value.as_bytes()].concat())
             .collect()
     }
 }
@@ -63,6 +64,7 @@ pub struct LabelMatcher {
     match_op: MatchOp,
     value: LabelValue,
     re: Option<Regex>,
+    cidr: Option<Cidr>,
 }
 
 impl LabelMatcher {
@@ -83,11 +85,17 @@ impl LabelMatcher {
             _ => None,
         };
 
+        let cidr = match match_op {
+            MatchOp::EqlIp | MatchOp::NeqIp => Some(value.parse::<Cidr>()?),
+            _ => None,
+        };
+
         Ok(Self {
             label,
             match_op,
             value,
             re,
+            cidr,
         })
     }
 
@@ -103,6 +111,7 @@ impl LabelMatcher {
             match_op: MatchOp::Eql,
             value: name,
             re: None,
+            cidr: None,
         }
     }
 
@@ -136,8 +145,19 @@ impl LabelMatcher {
                 .as_ref()
                 .expect("some regex is always expected for this type of matcher")
                 .is_match(v),
+            MatchOp::EqlIp => self.matches_cidr(v),
+            MatchOp::NeqIp => !self.matches_cidr(v),
         }
     }
+
+    // Non-IP values are in no network.
+    fn matches_cidr(&self, v: &str) -> bool {
+        let cidr = self
+            .cidr
+            .as_ref()
+            .expect("some CIDR is always expected for this type of matcher");
+        parse_ip(v).is_some_and(|ip| cidr.contains(&ip))
+    }
 }
 
 impl PartialEq for LabelMatcher {
@@ -152,6 +172,9 @@ pub enum MatchOp {
     Neq,
     EqlRe,
     NeqRe,
+    /// `label=ip("10.0.0.0/8")` - the label is an address in the network.
+    EqlIp,
+    NeqIp,
 }
 
 impl std::convert::TryFrom<&str> for MatchOp {
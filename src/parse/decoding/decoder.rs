@@ -2,10 +2,12 @@ use std::collections::HashMap;
 
 use super::strategy::{DecodingResult, DecodingStrategy};
 use crate::error::Result;
+use crate::input::Line;
+use crate::model::Labels;
 
 #[derive(Debug)]
 pub enum Entry {
-    Tuple(usize, Vec<String>),
+    Tuple(usize, Vec<Option<String>>),
     Dict(usize, HashMap<String, String>),
 }
 
@@ -18,7 +20,7 @@ impl Entry {
     }
 }
 
-type LineIter = Box<dyn std::iter::Iterator<Item = Result<(usize, Vec<u8>)>>>;
+type LineIter = Box<dyn std::iter::Iterator<Item = Result<Line>>>;
 
 pub struct Decoder {
     inner: LineIter,
@@ -31,19 +33,20 @@ impl Decoder {
     }
 }
 
+/// Entries come with the source labels of their lines.
 impl std::iter::Iterator for Decoder {
-    type Item = Result<Entry>;
+    type Item = Result<(Entry, Labels)>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let (line_no, line) = match self.inner.next() {
-            Some(Ok((line_no, line))) => (line_no, line),
+        let (line_no, line, labels) = match self.inner.next() {
+            Some(Ok(line)) => line,
             Some(Err(e)) => return Some(Err(e)),
             None => return None, // EOF
         };
 
         match self.strategy.decode(&line) {
-            Ok(DecodingResult::Tuple(v)) => Some(Ok(Entry::Tuple(line_no, v))),
-            Ok(DecodingResult::Dict(v)) => Some(Ok(Entry::Dict(line_no, v))),
+            Ok(DecodingResult::Tuple(v)) => Some(Ok((Entry::Tuple(line_no, v), labels))),
+            Ok(DecodingResult::Dict(v)) => Some(Ok((Entry::Dict(line_no, v), labels))),
             Err(e) => Some(Err(("line decoding failed", e).into())),
         }
     }
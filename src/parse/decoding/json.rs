@@ -5,17 +5,12 @@ use serde_json::{self, Map, Value};
 use super::strategy::{DecodingResult, DecodingStrategy};
 use crate::error::{Error, Result};
 
+#[derive(Default)]
 pub struct JSONDecodingStrategy {}
 
-impl Default for JSONDecodingStrategy {
-    fn default() -> Self {
-        Self {}
-    }
-}
-
 impl JSONDecodingStrategy {
     fn decode_tuple(&self, tuple: Vec<Value>) -> Result<DecodingResult> {
-        let items: Vec<String> = tuple
+        let items: Vec<Option<String>> = tuple
             .iter()
             .filter_map(|v| match v {
                 Value::Bool(b) => Some(b.to_string()),
@@ -24,6 +19,7 @@ impl JSONDecodingStrategy {
                 Value::String(s) => Some(s.to_string()),
                 _ => None,
             })
+            .map(Some)
             .collect();
 
         Ok(DecodingResult::Tuple(items))
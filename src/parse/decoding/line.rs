@@ -0,0 +1,14 @@
+use super::strategy::{DecodingResult, DecodingStrategy};
+use crate::error::Result;
+
+/// Keeps the line as is, i.e. as a single-field tuple. Used when
+/// the decoding is up to the mapping (see `switch`).
+#[derive(Default)]
+pub struct LineDecodingStrategy {}
+
+impl DecodingStrategy for LineDecodingStrategy {
+    fn decode(&self, line: &[u8]) -> Result<DecodingResult> {
+        let line = String::from_utf8(line.to_owned()).map_err(|e| ("non UTF-8 line", e))?;
+        Ok(DecodingResult::Tuple(vec![Some(line)]))
+    }
+}
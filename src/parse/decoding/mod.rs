@@ -1,9 +1,13 @@
 mod decoder;
 mod json;
+mod line;
+mod redact;
 mod regex;
 mod strategy;
 
 pub use self::regex::*;
 pub use decoder::*;
 pub use json::*;
+pub use line::*;
+pub use redact::*;
 pub use strategy::*;
@@ -0,0 +1,49 @@
+use super::strategy::{DecodingResult, DecodingStrategy};
+use crate::error::{Error, Result};
+use crate::parse::mapping::apply_transform;
+use crate::program::{FieldLoc, Redactor};
+
+/// Applies the redact stage right after decoding. Fields missing
+/// in the line are simply skipped.
+pub struct RedactingStrategy {
+    inner: Box<dyn DecodingStrategy>,
+    redactor: Redactor,
+}
+
+impl RedactingStrategy {
+    pub fn new(inner: Box<dyn DecodingStrategy>, redactor: Redactor) -> Self {
+        Self { inner, redactor }
+    }
+}
+
+impl DecodingStrategy for RedactingStrategy {
+    fn decode(&self, line: &[u8]) -> Result<DecodingResult> {
+        let mut decoded = self.inner.decode(line)?;
+
+        for field in self.redactor.fields.iter() {
+            let value = match (&mut decoded, &field.loc) {
+                (DecodingResult::Tuple(tuple), FieldLoc::Position(idx)) => {
+                    tuple.get_mut(*idx).and_then(Option::as_mut)
+                }
+                (DecodingResult::Dict(dict), FieldLoc::Name(name)) => dict.get_mut(name),
+                (DecodingResult::Tuple(_), FieldLoc::Name(_)) => {
+                    return Err(Error::new("tuple entry cannot be redacted by named fields"))
+                }
+                (DecodingResult::Dict(_), FieldLoc::Position(_)) => {
+                    return Err(Error::new(
+                        "dict entry cannot be redacted by positional fields",
+                    ))
+                }
+            };
+
+            if let Some(value) = value {
+                *value = field
+                    .transforms
+                    .iter()
+                    .fold(std::mem::take(value), |v, t| apply_transform(&v, t));
+            }
+        }
+
+        Ok(decoded)
+    }
+}
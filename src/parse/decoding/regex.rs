@@ -3,6 +3,7 @@ use regex;
 use super::strategy::{DecodingResult, DecodingStrategy};
 use crate::error::Result;
 
+#[derive(Clone)]
 pub struct RegexDecodingStrategy {
     re: regex::bytes::Regex,
 }
@@ -24,9 +25,11 @@ impl DecodingStrategy for RegexDecodingStrategy {
         Ok(DecodingResult::Tuple(
             caps.iter()
                 .skip((self.re.captures_len() > 1) as usize)
+                // Groups that didn't participate in the match (e.g. '(\d+)?') are absent.
                 .map(|c| {
-                    String::from_utf8(c.unwrap().as_bytes().to_owned())
-                        .expect("only UTF-8 is supported")
+                    c.map(|c| {
+                        String::from_utf8(c.as_bytes().to_owned()).expect("only UTF-8 is supported")
+                    })
                 })
                 .collect(),
         ))
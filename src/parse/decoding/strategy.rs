@@ -4,10 +4,11 @@ use crate::error::Result;
 
 #[derive(Debug)]
 pub enum DecodingResult {
-    Tuple(Vec<String>),
+    /// None stands for a field that is absent, e.g. an optional regex group that didn't match.
+    Tuple(Vec<Option<String>>),
     Dict(HashMap<String, String>),
 }
 
-pub trait DecodingStrategy {
+pub trait DecodingStrategy: Send + Sync {
     fn decode(&self, line: &[u8]) -> Result<DecodingResult>;
 }
@@ -0,0 +1,131 @@
+use std::time::Duration;
+
+use chrono::Utc;
+
+use crate::error::{Error, Result};
+use crate::model::{Timestamp, TimestampTrait};
+use crate::utils::parse::parse_duration;
+use crate::utils::time::{TimeExpr, Timezone};
+
+/// Where records lacking a timestamp get one from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimestampSource {
+    /// arrival - the wall-clock time the record was mapped at (for live pipes).
+    Arrival,
+    /// line, line*100ms - the line number on a virtual clock starting at the epoch.
+    LineNo { step: Duration },
+    /// 2021-06-01T00:00:00Z+1s, now+10ms - the start plus the step for every next record
+    /// (records dropped by filters don't count).
+    Sequence { start: TimeExpr, step: Duration },
+}
+
+impl std::str::FromStr for TimestampSource {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        if s == "arrival" {
+            return Ok(TimestampSource::Arrival);
+        }
+
+        if let Some(step) = s.strip_prefix("line") {
+            return match step.trim_start().strip_prefix('*') {
+                Some(step) => Ok(TimestampSource::LineNo {
+                    step: parse_duration(step.trim())?,
+                }),
+                None if step.is_empty() => Ok(TimestampSource::LineNo {
+                    step: Duration::from_secs(1),
+                }),
+                None => Err(Error::new("expected line[*<step>]")),
+            };
+        }
+
+        match s.rsplit_once('+') {
+            Some((start, step)) => Ok(TimestampSource::Sequence {
+                start: start.parse()?,
+                step: parse_duration(step.trim())?,
+            }),
+            None => Err(Error::new(
+                "timestamp source must be arrival, line[*<step>], or <start>+<step>",
+            )),
+        }
+    }
+}
+
+/// Stamps records according to the timestamp source.
+pub struct Clock {
+    source: TimestampSource,
+    next: Timestamp,
+}
+
+impl Clock {
+    /// The sequence start is resolved right away, i.e. `now` is the start of the run.
+    pub fn new(source: TimestampSource, tz: &Timezone, year: Option<i32>) -> Self {
+        let next = match source {
+            TimestampSource::Sequence { start, .. } => start.resolve(Utc::now(), tz, year),
+            _ => 0,
+        };
+        Self { source, next }
+    }
+
+    /// The sequence doesn't move until the record is kept (see tick()).
+    pub fn timestamp(&self, line_no: usize) -> Timestamp {
+        match self.source {
+            TimestampSource::Arrival => Utc::now().timestamp_millis(),
+            TimestampSource::LineNo { step } => {
+                line_no as Timestamp * step.as_millis() as Timestamp
+            }
+            TimestampSource::Sequence { .. } => self.next,
+        }
+    }
+
+    /// Moves the sequence to the next record.
+    pub fn tick(&mut self) {
+        if let TimestampSource::Sequence { step, .. } = self.source {
+            self.next = self.next.add(step);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clock() -> std::result::Result<(), Box<dyn std::error::Error>> {
+        let tz = Timezone::default();
+
+        let mut clock = Clock::new("line".parse()?, &tz, None);
+        assert_eq!(42000, clock.timestamp(42));
+        clock.tick();
+        assert_eq!(42000, clock.timestamp(42));
+
+        let clock = Clock::new("line * 10ms".parse()?, &tz, None);
+        assert_eq!(420, clock.timestamp(42));
+        assert_eq!(430, clock.timestamp(43));
+
+        let mut clock = Clock::new("2021-06-01T00:00:00Z+1s".parse()?, &tz, None);
+        assert_eq!(1622505600000, clock.timestamp(42));
+        clock.tick();
+        assert_eq!(1622505601000, clock.timestamp(100));
+
+        let mut clock = Clock::new("2021-06-01 00:00:00 + 500ms".parse()?, &tz, None);
+        assert_eq!(1622505600000, clock.timestamp(1));
+        assert_eq!(1622505600000, clock.timestamp(2)); // not kept
+        clock.tick();
+        assert_eq!(1622505600500, clock.timestamp(3));
+
+        let before = Utc::now().timestamp_millis();
+        let clock = Clock::new("arrival".parse()?, &tz, None);
+        assert!(clock.timestamp(1) >= before);
+
+        for input in &["lines", "line*", "2021-06-01", "tomorrow+1s", "now+1x"] {
+            assert!(
+                input.parse::<TimestampSource>().is_err(),
+                "while parsing {}",
+                input
+            );
+        }
+        Ok(())
+    }
+}
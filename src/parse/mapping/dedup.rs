@@ -0,0 +1,165 @@
+use std::cell::Cell;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::rc::Rc;
+use std::time::Duration;
+
+use super::record::Record;
+use crate::model::{Timestamp, TimestampTrait};
+use crate::program::Dedup;
+
+// Joins the key parts (can't occur in sane log fields).
+const SEP: char = '\u{1f}';
+
+/// Drops records repeating the key of a record seen within the window
+/// (in record time). Records without a timestamp or lacking some of the
+/// key fields are never dropped.
+pub struct Deduplicator {
+    fields: Vec<String>,
+    window: Duration,
+    /// Key -> timestamp of its first record within the window.
+    seen: HashMap<String, Timestamp>,
+    /// Oldest first, even if records come out of the timestamp order.
+    expiry: BinaryHeap<Reverse<(Timestamp, String)>>,
+    newest: Timestamp,
+    dropped: Rc<Cell<usize>>,
+}
+
+impl Deduplicator {
+    pub fn new(dedup: Dedup) -> Self {
+        Self {
+            fields: dedup.fields,
+            window: dedup.window,
+            seen: HashMap::new(),
+            expiry: BinaryHeap::new(),
+            newest: Timestamp::MIN,
+            dropped: Rc::new(Cell::new(0)),
+        }
+    }
+
+    /// Number of dropped duplicates (shared, so it can be read after
+    /// the deduplicator is moved into the pipeline).
+    pub fn dropped(&self) -> Rc<Cell<usize>> {
+        Rc::clone(&self.dropped)
+    }
+
+    pub fn is_duplicate(&mut self, record: &Record) -> bool {
+        let (timestamp, key) = match (record.timestamp(), self.key(record)) {
+            (Some(timestamp), Some(key)) => (timestamp, key),
+            _ => return false,
+        };
+
+        if let Some(first) = self.seen.get(&key) {
+            if first.add(self.window) >= timestamp && timestamp.add(self.window) >= *first {
+                self.dropped.set(self.dropped.get() + 1);
+                return true;
+            }
+        }
+
+        self.seen.insert(key.clone(), timestamp);
+        self.expiry.push(Reverse((timestamp, key)));
+        self.expire(timestamp);
+        false
+    }
+
+    fn expire(&mut self, timestamp: Timestamp) {
+        self.newest = self.newest.max(timestamp);
+        let horizon = self.newest.sub(self.window);
+
+        while let Some(Reverse((timestamp, _))) = self.expiry.peek() {
+            if *timestamp >= horizon {
+                break;
+            }
+            let Reverse((timestamp, key)) = self.expiry.pop().unwrap();
+            // The key could have been seen again since then.
+            if self.seen.get(&key) == Some(&timestamp) {
+                self.seen.remove(&key);
+            }
+        }
+    }
+
+    fn key(&self, record: &Record) -> Option<String> {
+        let mut key = String::new();
+
+        if self.fields.is_empty() {
+            let mut labels: Vec<_> = record.labels().iter().collect();
+            labels.sort();
+            let mut values: Vec<_> = record.values().iter().collect();
+            values.sort_by_key(|(name, _)| *name);
+
+            for (name, value) in labels {
+                key.push_str(&format!("{}{}={}", SEP, name, value));
+            }
+            for (name, value) in values {
+                key.push_str(&format!("{}{}={}", SEP, name, value));
+            }
+            return Some(key);
+        }
+
+        for field in self.fields.iter() {
+            match (record.labels().get(field), record.values().get(field)) {
+                (Some(label), _) => key.push_str(label),
+                (None, Some(value)) => key.push_str(&value.to_string()),
+                (None, None) => return None,
+            }
+            key.push(SEP);
+        }
+        Some(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Labels;
+    use crate::parse::mapping::record::Values;
+
+    fn record(timestamp: Option<Timestamp>, id: &str, status: f64) -> Record {
+        let mut labels = Labels::new();
+        labels.insert("id".to_owned(), id.to_owned());
+        let mut values = Values::new();
+        values.insert("status".to_owned(), status);
+        Record::new(0, timestamp, labels, values)
+    }
+
+    #[test]
+    fn test_deduplicator() {
+        let mut dedup = Deduplicator::new(Dedup {
+            fields: vec!["id".to_owned()],
+            window: Duration::from_secs(10),
+        });
+
+        #[rustfmt::skip]
+        let tests = [
+            (record(Some(0), "a", 200.0), false),
+            (record(Some(5_000), "a", 500.0), true),
+            (record(Some(5_000), "b", 200.0), false),
+            (record(Some(2_000), "b", 200.0), true),  // late but within the window
+            (record(Some(10_000), "a", 200.0), true),
+            (record(Some(10_001), "a", 200.0), false), // window is over
+            (record(None, "b", 200.0), false),
+        ];
+        for (i, (record, expected)) in tests.iter().enumerate() {
+            assert_eq!(*expected, dedup.is_duplicate(record), "record #{}", i);
+        }
+        assert_eq!(3, dedup.dropped().get());
+
+        let mut dedup = Deduplicator::new(Dedup {
+            fields: vec![],
+            window: Duration::from_secs(10),
+        });
+        assert!(!dedup.is_duplicate(&record(Some(0), "a", 200.0)));
+        assert!(dedup.is_duplicate(&record(Some(0), "a", 200.0)));
+        assert!(!dedup.is_duplicate(&record(Some(0), "a", 500.0)));
+        assert!(dedup.is_duplicate(&record(Some(1_000), "a", 200.0)));
+        assert!(!dedup.is_duplicate(&record(Some(10_001), "a", 200.0)));
+
+        // Records lacking the key fields are kept.
+        let mut dedup = Deduplicator::new(Dedup {
+            fields: vec!["missing".to_owned()],
+            window: Duration::from_secs(10),
+        });
+        assert!(!dedup.is_duplicate(&record(Some(0), "a", 200.0)));
+        assert!(!dedup.is_duplicate(&record(Some(0), "a", 200.0)));
+    }
+}
@@ -0,0 +1,294 @@
+use std::cmp::Ordering;
+
+use super::record::Record;
+use super::strategy::get_entry_field;
+use crate::error::{Error, Result};
+use crate::model::SampleValue;
+use crate::parse::Entry;
+use crate::program::{
+    FieldExpr, FieldFunc, FieldLoc, FieldOp, Pattern, SecretKey, SharedMiner, TemplatePart,
+    Transform, SCRUBBED,
+};
+use crate::utils::hash::fnv1a;
+use crate::utils::net::{parse_ip, Cidr};
+use crate::utils::redact::{hmac, mask_email, truncate_ip};
+use crate::utils::route::url_path;
+
+/// Result of a computed field. Numbers go to the record values, strings become labels.
+#[derive(Debug, PartialEq)]
+pub enum Computed {
+    Number(SampleValue),
+    String(String),
+}
+
+impl Computed {
+    fn number(&self) -> Result<SampleValue> {
+        match self {
+            Computed::Number(n) => Ok(*n),
+            Computed::String(s) => s
+                .parse::<SampleValue>()
+                .map_err(|_| Error::new("non-numeric operand in field expression")),
+        }
+    }
+
+    fn into_string(self) -> String {
+        match self {
+            Computed::Number(n) => n.to_string(),
+            Computed::String(s) => s,
+        }
+    }
+
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            Computed::Number(n) => *n != 0.0 && !n.is_nan(),
+            Computed::String(s) => match s.parse::<SampleValue>() {
+                Ok(n) => n != 0.0 && !n.is_nan(),
+                Err(_) => !s.is_empty(),
+            },
+        }
+    }
+
+    fn from_bool(b: bool) -> Self {
+        Computed::Number(if b { 1.0 } else { 0.0 })
+    }
+}
+
+/// Something field expressions can be evaluated against: raw entries
+/// at the mapping stage or mapped records at the filtering stage.
+pub trait FieldSource {
+    fn field(&self, loc: &FieldLoc) -> Result<Computed>;
+}
+
+impl FieldSource for Entry {
+    fn field(&self, loc: &FieldLoc) -> Result<Computed> {
+        // Entry fields are kept as is (e.g. "007" stays "007") until
+        // an operator needs a number.
+        Ok(Computed::String(get_entry_field(self, loc)?))
+    }
+}
+
+impl FieldSource for Record {
+    fn field(&self, loc: &FieldLoc) -> Result<Computed> {
+        let name = match loc {
+            FieldLoc::Name(name) => name,
+            FieldLoc::Position(_) => {
+                return Err(Error::new(
+                    "records cannot be filtered by positional fields",
+                ))
+            }
+        };
+        if let Some(label) = self.labels().get(name) {
+            return Ok(Computed::String(label.clone()));
+        }
+        match self.values().get(name) {
+            Some(value) => Ok(Computed::Number(*value)),
+            None => Err(Error::new("record field not found")),
+        }
+    }
+}
+
+pub fn eval(expr: &FieldExpr, source: &dyn FieldSource) -> Result<Computed> {
+    match expr {
+        FieldExpr::Field(loc) => source.field(loc),
+        FieldExpr::Number(n) => Ok(Computed::Number(*n)),
+        FieldExpr::String(s) => Ok(Computed::String(s.clone())),
+        FieldExpr::Negation(expr) => Ok(Computed::Number(-eval(expr, source)?.number()?)),
+        FieldExpr::Not(expr) => Ok(Computed::from_bool(!eval(expr, source)?.is_truthy())),
+        FieldExpr::Match(expr, Pattern(regex)) => Ok(Computed::from_bool(
+            regex.is_match(&eval(expr, source)?.into_string()),
+        )),
+        // Short-circuiting, e.g. '.has_user and .user == "root"'.
+        FieldExpr::Binary(lhs, FieldOp::And, rhs) => Ok(Computed::from_bool(
+            eval(lhs, source)?.is_truthy() && eval(rhs, source)?.is_truthy(),
+        )),
+        FieldExpr::Binary(lhs, FieldOp::Or, rhs) => Ok(Computed::from_bool(
+            eval(lhs, source)?.is_truthy() || eval(rhs, source)?.is_truthy(),
+        )),
+        FieldExpr::Binary(lhs, op, rhs) => eval_binary(eval(lhs, source)?, *op, eval(rhs, source)?),
+        FieldExpr::Call(FieldFunc::Concat, args) => {
+            let mut result = String::new();
+            for arg in args {
+                result.push_str(&eval(arg, source)?.into_string());
+            }
+            Ok(Computed::String(result))
+        }
+        FieldExpr::Transform(expr, transform) => Ok(Computed::String(apply_transform(
+            &eval(expr, source)?.into_string(),
+            transform,
+        ))),
+        // Non-IP values are in no network and in no bucket.
+        FieldExpr::InCidr(expr, cidrs) => Ok(Computed::from_bool(
+            match parse_ip(&eval(expr, source)?.into_string()) {
+                Some(ip) => cidrs.iter().any(|cidr| cidr.contains(&ip)),
+                None => false,
+            },
+        )),
+        FieldExpr::CidrBucket(expr, v4, v6) => Ok(Computed::String(
+            match parse_ip(&eval(expr, source)?.into_string()) {
+                Some(ip) => Cidr::of(ip, if ip.is_ipv4() { *v4 } else { *v6 }).to_string(),
+                None => String::new(),
+            },
+        )),
+        FieldExpr::Template(expr, part, SharedMiner(miner)) => {
+            let message = eval(expr, source)?.into_string();
+            let mut miner = miner.lock().expect("poisoned template miner");
+            let template = miner.add(&message);
+            Ok(Computed::String(match part {
+                TemplatePart::Pattern => template.pattern(),
+                TemplatePart::Id => template.id.clone(),
+            }))
+        }
+        FieldExpr::CidrLookup(expr, table) => Ok(Computed::String(
+            parse_ip(&eval(expr, source)?.into_string())
+                .and_then(|ip| table.lookup(&ip))
+                .unwrap_or_default()
+                .to_owned(),
+        )),
+    }
+}
+
+pub(crate) fn apply_transform(s: &str, transform: &Transform) -> String {
+    match transform {
+        Transform::Lower => s.to_lowercase(),
+        Transform::Upper => s.to_uppercase(),
+        Transform::Trim => s.trim().to_owned(),
+        Transform::Substr(start, len) => {
+            let chars = s.chars().skip(*start);
+            match len {
+                Some(len) => chars.take(*len).collect(),
+                None => chars.collect(),
+            }
+        }
+        Transform::RegexReplace(Pattern(regex), replacement) => {
+            regex.replace_all(s, replacement.as_str()).into_owned()
+        }
+        Transform::SplitPart(sep, n) => s.split(sep.as_str()).nth(n - 1).unwrap_or("").to_owned(),
+        Transform::UrlPath => url_path(s).to_owned(),
+        Transform::UrlQuery(key) => url_query(s)
+            .split('&')
+            .filter_map(|pair| pair.split_once('=').or(Some((pair, ""))))
+            .find(|(k, _)| k == key)
+            .map_or_else(String::new, |(_, v)| v.to_owned()),
+        Transform::Hash => format!("{:016x}", fnv1a(s.as_bytes())),
+        Transform::Hmac(SecretKey(key)) => hmac(s.as_bytes(), key),
+        // Whatever isn't an IP address is dropped rather than leaked.
+        Transform::IpTruncate(v4, v6) => truncate_ip(s, *v4, *v6).unwrap_or_default(),
+        Transform::MaskEmail => mask_email(s),
+        Transform::Scrub(Pattern(regex)) => regex.replace_all(s, SCRUBBED).into_owned(),
+        Transform::Route(table) => table.normalize(s),
+    }
+}
+
+fn url_query(url: &str) -> &str {
+    let url = &url[..url.find('#').unwrap_or(url.len())];
+    url.split_once('?').map_or("", |(_, query)| query)
+}
+
+fn eval_binary(lhs: Computed, op: FieldOp, rhs: Computed) -> Result<Computed> {
+    use FieldOp::*;
+
+    let result = match op {
+        Add => lhs.number()? + rhs.number()?,
+        Sub => lhs.number()? - rhs.number()?,
+        Mul => lhs.number()? * rhs.number()?,
+        Div => lhs.number()? / rhs.number()?,
+        Mod => lhs.number()? % rhs.number()?,
+        Pow => lhs.number()?.powf(rhs.number()?),
+        Eql | Neq | Gtr | Gte | Lss | Lte => {
+            // Numeric comparison if both sides look like numbers, lexicographic otherwise.
+            let ord = match (lhs.number(), rhs.number()) {
+                (Ok(l), Ok(r)) => l.partial_cmp(&r),
+                _ => Some(lhs.into_string().cmp(&rhs.into_string())),
+            };
+            let holds = match (op, ord) {
+                (Eql, Some(o)) => o == Ordering::Equal,
+                (Neq, o) => o != Some(Ordering::Equal),
+                (Gtr, Some(o)) => o == Ordering::Greater,
+                (Gte, Some(o)) => o != Ordering::Less,
+                (Lss, Some(o)) => o == Ordering::Less,
+                (Lte, Some(o)) => o != Ordering::Greater,
+                _ => false, // NaN
+            };
+            return Ok(Computed::from_bool(holds));
+        }
+        And | Or => unreachable!("logical operators are evaluated lazily"),
+    };
+    Ok(Computed::Number(result))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::program::parse_program;
+    use crate::program::FieldType;
+
+    #[test]
+    fn test_eval() -> std::result::Result<(), Box<dyn std::error::Error>> {
+        let mut dict = HashMap::new();
+        dict.insert("bytes".to_owned(), "1000".to_owned());
+        dict.insert("time".to_owned(), "0.5".to_owned());
+        dict.insert("host".to_owned(), "example.com".to_owned());
+        dict.insert("port".to_owned(), "080".to_owned());
+        dict.insert("method".to_owned(), "GET".to_owned());
+        dict.insert("ua".to_owned(), " Mozilla/5.0 ".to_owned());
+        dict.insert(
+            "url".to_owned(),
+            "https://example.com/api/v1/users/42/orders/7?page=2&q=foo#top".to_owned(),
+        );
+        let entry = Entry::Dict(1, dict);
+
+        #[rustfmt::skip]
+        let tests = [
+            (".bytes / .time", Ok(Computed::Number(2000.0))),
+            ("-.bytes + 2 * 3 ^ 2", Ok(Computed::Number(-982.0))),
+            (".bytes % 300 == 100", Ok(Computed::Number(1.0))),
+            (".port > 9", Ok(Computed::Number(1.0))),
+            (r#".method != "GET""#, Ok(Computed::Number(0.0))),
+            (r#".method < "POST""#, Ok(Computed::Number(1.0))),
+            (r#"concat(.host, ":", .port)"#, Ok(Computed::String("example.com:080".to_owned()))),
+            (r#"concat(.time * 2, "s")"#, Ok(Computed::String("1s".to_owned()))),
+            (".ua | trim | lower", Ok(Computed::String("mozilla/5.0".to_owned()))),
+            (".method | upper | substr(1)", Ok(Computed::String("ET".to_owned()))),
+            (".host | substr(0, 7)", Ok(Computed::String("example".to_owned()))),
+            (".url | url_path", Ok(Computed::String("/api/v1/users/42/orders/7".to_owned()))),
+            (r#".url | url_path | regex_replace("/\d+", "/:id")"#, Ok(Computed::String("/api/v1/users/:id/orders/:id".to_owned()))),
+            (r#".url | url_path | split_part("/", 3)"#, Ok(Computed::String("v1".to_owned()))),
+            (".url | route", Ok(Computed::String("/api/v1/users/:id/orders/:id".to_owned()))),
+            (r#".url | route("/api/v1/users/:user_id/*")"#, Ok(Computed::String("/api/v1/users/:user_id/*".to_owned()))),
+            (r#".url | url_query("q")"#, Ok(Computed::String("foo".to_owned()))),
+            (r#".url | url_query("missing")"#, Ok(Computed::String("".to_owned()))),
+            (".host | hash", Ok(Computed::String("576846634e2714c6".to_owned()))),
+            (r#"concat("10.1.2.3:", .port) | ip_truncate"#, Ok(Computed::String("10.1.2.0".to_owned()))),
+            (".host | ip_truncate", Ok(Computed::String("".to_owned()))),
+            (r#"in_cidr("10.1.2.3", "192.168.0.0/16", "10.0.0.0/8")"#, Ok(Computed::Number(1.0))),
+            (r#"in_cidr(concat("[2001:db8::1]:", .port), "2001:db9::/32")"#, Ok(Computed::Number(0.0))),
+            (r#"in_cidr(.host, "0.0.0.0/0")"#, Ok(Computed::Number(0.0))),
+            (r#"cidr_bucket("203.0.113.42", 24)"#, Ok(Computed::String("203.0.113.0/24".to_owned()))),
+            (r#"cidr_bucket("2001:db8:1:2::1", 24, 32)"#, Ok(Computed::String("2001:db8::/32".to_owned()))),
+            ("cidr_bucket(.host, 24)", Ok(Computed::String("".to_owned()))),
+            (r#"template(concat(.method, " ", .url, " took ", .time))"#, Ok(Computed::String("GET <*> took <*>".to_owned()))),
+            (r#"template(concat(.method, " ", .host), 0.5) | upper"#, Ok(Computed::String("GET EXAMPLE.COM".to_owned()))),
+            (r#"concat("jane@", .host) | mask_email"#, Ok(Computed::String("j***@example.com".to_owned()))),
+            (r#".url | scrub("\d+")"#, Ok(Computed::String("https://example.com/api/v[REDACTED]/users/[REDACTED]/orders/[REDACTED]?page=[REDACTED]&q=foo#top".to_owned()))),
+            (r#".method =~ "^G" and not .port == 80"#, Ok(Computed::Number(0.0))),
+            (r#".method !~ "T$" or .missing"#, Err("dict entry field not found".to_owned())),
+            (r#".bytes > 1 or .missing"#, Ok(Computed::Number(1.0))),
+            (".host * 2", Err("non-numeric operand in field expression".to_owned())),
+            (".missing + 1", Err("dict entry field not found".to_owned())),
+        ];
+
+        for (input, expected) in tests.iter() {
+            let program = format!("json | map {{ {} as result }}", input);
+            let mapper = parse_program(&program)?.mapper.unwrap();
+            let expr = match &mapper.fields[0].typ {
+                FieldType::Computed(expr) => expr,
+                typ => panic!("unexpected field type {:?} while parsing {}", typ, input),
+            };
+            let actual = eval(expr, &entry).map_err(|e| e.to_string());
+            assert_eq!(*expected, actual, "while evaluating {}", input);
+        }
+        Ok(())
+    }
+}
@@ -1,27 +1,101 @@
+use super::clock::Clock;
+use super::dedup::Deduplicator;
+use super::eval::eval;
 use super::record::Record;
 use super::strategy::MappingStrategy;
 use crate::error::Result;
-use crate::model::Timestamp;
+use crate::input::Progress;
+use crate::model::{Labels, Timestamp};
 use crate::parse::Entry;
-use crate::program::Mapper as MappingRules;
+use crate::program::FieldExpr;
 use crate::utils::time::TimeRange;
 
+type RecordIter = Box<dyn std::iter::Iterator<Item = Result<Record>>>;
+
 pub struct Mapper {
-    entries: Box<dyn std::iter::Iterator<Item = Result<Entry>>>,
-    strategy: MappingStrategy,
+    records: RecordIter,
     range: TimeRange,
+    filter: Option<FieldExpr>,
+    clock: Option<Clock>,
+    dedup: Option<Box<Deduplicator>>,
+    progress: Option<Progress>,
 }
 
 impl Mapper {
+    /// Entries come with the labels of their lines (see Decoder).
     pub fn new(
-        entries: Box<dyn std::iter::Iterator<Item = Result<Entry>>>,
-        mapping: MappingRules,
+        entries: Box<dyn std::iter::Iterator<Item = Result<(Entry, Labels)>>>,
+        strategy: MappingStrategy,
         range: Option<TimeRange>,
     ) -> Self {
+        Self::from_records(
+            Box::new(entries.map(move |entry| {
+                let (entry, labels) = entry?;
+                let mut record = strategy.map(entry)?;
+                record.add_source_labels(labels);
+                Ok(record)
+            })),
+            range,
+        )
+    }
+
+    /// Wraps already mapped (but not yet filtered by time) records,
+    /// e.g. produced by a parallel pipeline.
+    pub fn from_records(records: RecordIter, range: Option<TimeRange>) -> Self {
         Self {
-            entries,
-            strategy: MappingStrategy::new(mapping),
+            records,
             range: range.unwrap_or_else(TimeRange::infinity),
+            filter: None,
+            clock: None,
+            dedup: None,
+            progress: None,
+        }
+    }
+
+    /// Drops records not matching the `where` expression. Records lacking
+    /// the fields the expression refers to don't match either.
+    pub fn with_filter(mut self, filter: FieldExpr) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    /// Stamps records lacking a timestamp (e.g. mapped from a file without
+    /// time fields), so that they can be queried too. Sequence clocks don't
+    /// move for the records dropped by the filter or dedup.
+    pub fn with_clock(mut self, clock: Clock) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Drops duplicates of the records (that passed the filter) seen recently.
+    pub fn with_dedup(mut self, dedup: Deduplicator) -> Self {
+        self.dedup = Some(Box::new(dedup));
+        self
+    }
+
+    /// Counts the consumed records (and errors), one per input line. The
+    /// record past the end of the time range isn't consumed.
+    pub fn with_progress(mut self, progress: Progress) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    fn advance(&self) {
+        if let Some(progress) = self.progress.as_ref() {
+            progress.advance();
+        }
+    }
+
+    fn tick(&mut self, clocked: bool) {
+        if let (true, Some(clock)) = (clocked, self.clock.as_mut()) {
+            clock.tick();
+        }
+    }
+
+    fn matches(&self, record: &Record) -> bool {
+        match self.filter {
+            Some(ref filter) => eval(filter, record).is_ok_and(|v| v.is_truthy()),
+            None => true,
         }
     }
 }
@@ -31,28 +105,48 @@ impl std::iter::Iterator for Mapper {
 
     fn next(&mut self) -> Option<Self::Item> {
         loop {
-            let entry = match self.entries.next() {
-                Some(Ok(entry)) => entry,
-                Some(Err(e)) => return Some(Err(e)),
+            let mut record = match self.records.next() {
+                Some(Ok(record)) => record,
+                Some(Err(e)) => {
+                    self.advance();
+                    return Some(Err(e));
+                }
                 None => return None, // EOF
             };
 
-            let record = match self.strategy.map(entry) {
-                Ok(record) => record,
-                Err(e) => return Some(Err(e)),
+            let clocked = match (record.timestamp(), self.clock.as_ref()) {
+                (None, Some(clock)) => {
+                    record.set_timestamp(clock.timestamp(record.line_no()));
+                    true
+                }
+                _ => false,
             };
 
+            if record.timestamp().unwrap_or(Timestamp::MIN)
+                > self.range.end().unwrap_or(Timestamp::MAX)
+            {
+                return None; // not a EOF but we are out of requested range.
+            }
+            self.advance();
+
             if record.timestamp().unwrap_or(Timestamp::MAX)
                 < self.range.start().unwrap_or(Timestamp::MIN)
             {
+                self.tick(clocked);
                 continue;
             }
-            if record.timestamp().unwrap_or(Timestamp::MIN)
-                > self.range.end().unwrap_or(Timestamp::MAX)
-            {
-                return None; // not a EOF but we are out of requested range.
+
+            if !self.matches(&record) {
+                continue;
+            }
+
+            if let Some(dedup) = self.dedup.as_mut() {
+                if dedup.is_duplicate(&record) {
+                    continue;
+                }
             }
 
+            self.tick(clocked);
             return Some(Ok(record));
         }
     }
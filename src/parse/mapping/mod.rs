@@ -1,6 +1,13 @@
+mod clock;
+mod dedup;
+mod eval;
 mod mapper;
 mod record;
 mod strategy;
 
+pub use clock::*;
+pub use dedup::*;
+pub(crate) use eval::apply_transform;
 pub use mapper::*;
 pub use record::*;
+pub use strategy::MappingStrategy;
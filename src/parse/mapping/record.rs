@@ -1,15 +1,25 @@
 use std::collections::HashMap;
 
-use crate::model::{Labels, MetricName, SampleValue, Timestamp};
+use crate::model::{LabelValue, Labels, MetricName, SampleValue, Timestamp};
 
 pub type Values = HashMap<MetricName, SampleValue>;
 
+/// Histogram bucket counter, i.e. a value of the `<name>_bucket` series
+/// with the extra `le` label. The `_sum` and `_count` ones are plain values.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bucket {
+    pub name: MetricName,
+    pub le: LabelValue,
+    pub count: SampleValue,
+}
+
 #[derive(Debug)]
 pub struct Record {
     line_no: usize,
     timestamp: Option<Timestamp>,
     labels: Labels,
     values: Values,
+    buckets: Vec<Bucket>,
 }
 
 impl Record {
@@ -24,9 +34,15 @@ impl Record {
             timestamp,
             labels,
             values,
+            buckets: Vec::new(),
         }
     }
 
+    pub(super) fn with_buckets(mut self, buckets: Vec<Bucket>) -> Self {
+        self.buckets = buckets;
+        self
+    }
+
     #[inline]
     pub fn line_no(&self) -> usize {
         self.line_no
@@ -37,13 +53,30 @@ impl Record {
         self.timestamp
     }
 
+    #[inline]
+    pub(super) fn set_timestamp(&mut self, timestamp: Timestamp) {
+        self.timestamp = Some(timestamp);
+    }
+
     #[inline]
     pub fn labels(&self) -> &Labels {
         &self.labels
     }
 
+    /// Adds the labels of the record's line. Mapped fields take precedence.
+    pub fn add_source_labels(&mut self, labels: Labels) {
+        for (name, value) in labels {
+            self.labels.entry(name).or_insert(value);
+        }
+    }
+
     #[inline]
     pub fn values(&self) -> &Values {
         &self.values
     }
+
+    #[inline]
+    pub fn buckets(&self) -> &[Bucket] {
+        &self.buckets
+    }
 }
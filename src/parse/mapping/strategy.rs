@@ -1,23 +1,94 @@
-use super::record::{Record, Values};
+use super::eval::{eval, Computed};
+use super::record::{Bucket, Record, Values};
 use crate::error::{Error, Result};
 use crate::model::{Labels, SampleValue, Timestamp};
-use crate::parse::Entry;
-use crate::program::{FieldLoc, FieldType, Mapper as MappingRules, MapperField};
-use crate::utils::time::{parse_time, try_parse_time};
+use crate::parse::{DecodingResult, DecodingStrategy, Entry, RegexDecodingStrategy};
+use crate::program::{FieldLoc, FieldType, Histogram, Mapper as MappingRules, SwitchArm, Wildcard};
+use crate::utils::net::parse_ip;
+use crate::utils::parse::{parse_bytes, parse_duration_value};
+use crate::utils::time::{parse_time, TimeFormatGuesser, Timezone};
 
-pub struct MappingStrategy {
-    mapping: MappingRules,
+/// Turns decoded entries into records. Clones don't share the state
+/// (e.g. of the time format guessing).
+#[derive(Clone)]
+pub struct MappingStrategy(Rules);
+
+#[derive(Clone)]
+enum Rules {
+    Single(RulesMapping),
+    /// Entries are undecoded lines (see LineDecodingStrategy), and
+    /// the first arm with a matching regex decodes and maps them.
+    Switch(Vec<(RegexDecodingStrategy, RulesMapping)>),
 }
 
 impl MappingStrategy {
     pub fn new(mapping: MappingRules) -> Self {
-        Self { mapping }
+        Self(Rules::Single(RulesMapping::new(mapping)))
+    }
+
+    pub fn switch(arms: Vec<SwitchArm>) -> Result<Self> {
+        let arms = arms
+            .into_iter()
+            .map(|arm| {
+                Ok((
+                    RegexDecodingStrategy::new(&arm.regex)?,
+                    RulesMapping::new(arm.mapper),
+                ))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self(Rules::Switch(arms)))
     }
 
     pub fn map(&self, entry: Entry) -> Result<Record> {
+        let arms = match self.0 {
+            Rules::Single(ref mapping) => return mapping.map(entry),
+            Rules::Switch(ref arms) => arms,
+        };
+
+        let (line_no, line) = match entry {
+            Entry::Tuple(line_no, mut tuple) if tuple.len() == 1 => match tuple.remove(0) {
+                Some(line) => (line_no, line),
+                None => return Err(Error::new("switch expects undecoded lines")),
+            },
+            _ => return Err(Error::new("switch expects undecoded lines")),
+        };
+        for (decoding, mapping) in arms.iter() {
+            if let Ok(DecodingResult::Tuple(tuple)) = decoding.decode(line.as_bytes()) {
+                return mapping.map(Entry::Tuple(line_no, tuple));
+            }
+        }
+        Err(Error::new("no switch arm matched"))
+    }
+}
+
+#[derive(Clone)]
+struct RulesMapping {
+    mapping: MappingRules,
+    timezone: Timezone,
+    times: TimeFormatGuesser,
+    /// Auto fields looking like timestamps become the record timestamp
+    /// if the mapping has no explicit one.
+    promote_timestamps: bool,
+}
+
+impl RulesMapping {
+    fn new(mapping: MappingRules) -> Self {
+        Self {
+            timezone: mapping.timezone.unwrap_or_default(),
+            times: mapping
+                .year
+                .map(TimeFormatGuesser::with_year)
+                .unwrap_or_default(),
+            promote_timestamps: !mapping.has_timestamp(),
+            mapping,
+        }
+    }
+
+    fn map(&self, entry: Entry) -> Result<Record> {
         let mut timestamp = None;
         let mut values = Values::new();
         let mut labels = Labels::new();
+        let mut buckets = Vec::new();
 
         for field in self.mapping.fields.iter() {
             if let FieldType::Const(ref value) = field.typ {
@@ -25,16 +96,42 @@ impl MappingStrategy {
                 continue;
             }
 
-            let datum = get_entry_field(&entry, field)?;
+            if let FieldType::Computed(ref expr) = field.typ {
+                match eval(expr, &entry)? {
+                    Computed::Number(n) => {
+                        values.insert(field.end_name(), n);
+                    }
+                    // Empty labels are absent ones (e.g. a cidr_lookup() miss).
+                    Computed::String(s) if s.is_empty() => {
+                        if let Some(ref default) = field.default {
+                            labels.insert(field.end_name(), default.clone());
+                        }
+                    }
+                    Computed::String(s) => {
+                        labels.insert(field.end_name(), s);
+                    }
+                }
+                continue;
+            }
+
+            let datum = match find_entry_field(&entry, &field.loc)? {
+                Some(datum) => datum,
+                None => match (&field.default, field.optional) {
+                    (Some(default), _) => default.clone(),
+                    (None, true) => continue,
+                    (None, false) => return Err(missing_field_error(&entry)),
+                },
+            };
 
             match &field.typ {
                 FieldType::Auto => {
                     if let Ok(n) = datum.parse::<SampleValue>() {
                         values.insert(field.end_name(), n);
+                    } else if let Some(ts) = self.promote_timestamp(&timestamp, &datum) {
+                        timestamp = Some(ts);
                     } else {
                         labels.insert(field.end_name(), datum);
                     }
-                    // TODO: else if Ok(ts) = datum.parse::<Timestamp>() { ... }
                 }
                 FieldType::Number => {
                     if let Ok(n) = datum.parse::<SampleValue>() {
@@ -43,36 +140,125 @@ impl MappingStrategy {
                         return Err(Error::new("could not parse numeric field"));
                     }
                 }
+                FieldType::Duration => match parse_duration_value(&datum) {
+                    Ok(d) => {
+                        values.insert(field.end_name(), d.as_secs_f64());
+                    }
+                    Err(e) => return Err(("could not parse duration field", e).into()),
+                },
+                FieldType::Bytes => match parse_bytes(&datum) {
+                    Ok(n) => {
+                        values.insert(field.end_name(), n);
+                    }
+                    Err(e) => return Err(("could not parse byte size field", e).into()),
+                },
                 FieldType::String => {
                     labels.insert(field.end_name(), datum);
                 }
-                FieldType::Timestamp(format) => {
-                    timestamp = Some(parse_timestamp_field(&datum, format.as_deref())?);
+                FieldType::Ip => match parse_ip(&datum) {
+                    Some(ip) => {
+                        labels.insert(field.end_name(), ip.to_string());
+                    }
+                    None => return Err(Error::new("could not parse IP address field")),
+                },
+                FieldType::Timestamp(format, tz) => {
+                    let tz = tz.unwrap_or(self.timezone);
+                    timestamp = Some(match format {
+                        Some(format) => parse_time(&datum, format, &tz)?,
+                        None => self
+                            .times
+                            .parse(&datum, &tz)
+                            .ok_or_else(|| Error::new("couldn't guess time format"))?,
+                    });
                 }
                 _ => unreachable!(),
             }
+
+            if let Some(ref histogram) = field.histogram {
+                let name = field.end_name();
+                if let Some(value) = values.remove(&name) {
+                    observe(&mut values, &mut buckets, &name, histogram, value);
+                }
+            }
         }
 
-        Ok(Record::new(entry.line_no(), timestamp, labels, values))
-    }
-}
+        if let Some(ref wildcard) = self.mapping.wildcard {
+            let mut unmapped: Vec<_> =
+                unmapped_entry_fields(&entry, &self.mapping, wildcard).collect();
+            if self.promote_timestamps && timestamp.is_none() {
+                // Dict fields come in random order, but the promoted one must not.
+                unmapped.sort_by(|(a, _), (b, _)| a.cmp(b));
+            }
 
-fn get_entry_field(entry: &Entry, field: &MapperField) -> Result<String> {
-    match (entry, &field.loc) {
-        (Entry::Tuple(_, tuple), FieldLoc::Position(idx)) => {
-            if *idx > tuple.len() {
-                Err(Error::new("tuple entry index out of range"))
-            } else {
-                Ok(tuple[*idx].clone())
+            for (loc, datum) in unmapped {
+                let name = match loc {
+                    FieldLoc::Name(name) => name,
+                    FieldLoc::Position(pos) => format!("f{}", pos),
+                };
+                // Explicitly mapped fields take precedence.
+                if labels.contains_key(&name) || values.contains_key(&name) {
+                    continue;
+                }
+                if let Ok(n) = datum.parse::<SampleValue>() {
+                    values.insert(name, n);
+                } else if let Some(ts) = self.promote_timestamp(&timestamp, datum) {
+                    timestamp = Some(ts);
+                } else {
+                    labels.insert(name, datum.clone());
+                }
             }
         }
-        (Entry::Dict(_, dict), FieldLoc::Name(name)) => {
-            if let Some(datum) = dict.get(name) {
-                Ok(datum.clone())
-            } else {
-                Err(Error::new("dict entry field not found"))
-            }
+
+        Ok(Record::new(entry.line_no(), timestamp, labels, values).with_buckets(buckets))
+    }
+
+    /// Returns the timestamp an auto-typed field holds if it's the first one
+    /// of the record. Numbers are never promoted (think of epoch-like counters).
+    fn promote_timestamp(&self, timestamp: &Option<Timestamp>, datum: &str) -> Option<Timestamp> {
+        if !self.promote_timestamps || timestamp.is_some() || !looks_like_time(datum) {
+            return None;
         }
+        self.times.parse(datum, &self.timezone)
+    }
+}
+
+// Cheap check to avoid guessing formats of every label-like field.
+fn looks_like_time(s: &str) -> bool {
+    s.len() >= 8 && s.len() <= 40 && s.contains(':') && s.bytes().any(|c| c.is_ascii_digit())
+}
+
+// A single observation: every bucket the value falls into counts one.
+fn observe(
+    values: &mut Values,
+    buckets: &mut Vec<Bucket>,
+    name: &str,
+    histogram: &Histogram,
+    value: SampleValue,
+) {
+    let bucket = |le: String, count| Bucket {
+        name: format!("{}_bucket", name),
+        le,
+        count,
+    };
+    for bound in histogram.bounds.iter() {
+        let count = if value <= *bound { 1.0 } else { 0.0 };
+        buckets.push(bucket(bound.to_string(), count));
+    }
+    buckets.push(bucket("+Inf".to_owned(), 1.0));
+    values.insert(format!("{}_sum", name), value);
+    values.insert(format!("{}_count", name), 1.0);
+}
+
+pub(super) fn get_entry_field(entry: &Entry, loc: &FieldLoc) -> Result<String> {
+    find_entry_field(entry, loc)?.ok_or_else(|| missing_field_error(entry))
+}
+
+/// Returns None if the entry simply doesn't have the field,
+/// and an error if the field can't be looked up in such an entry.
+fn find_entry_field(entry: &Entry, loc: &FieldLoc) -> Result<Option<String>> {
+    match (entry, loc) {
+        (Entry::Tuple(_, tuple), FieldLoc::Position(idx)) => Ok(tuple.get(*idx).cloned().flatten()),
+        (Entry::Dict(_, dict), FieldLoc::Name(name)) => Ok(dict.get(name).cloned()),
         (Entry::Tuple(_, _), FieldLoc::Name(_)) => {
             Err(Error::new("tuple entry cannot be mapped with named fields"))
         }
@@ -82,12 +268,105 @@ fn get_entry_field(entry: &Entry, field: &MapperField) -> Result<String> {
     }
 }
 
-fn parse_timestamp_field(timestamp: &str, format: Option<&str>) -> Result<Timestamp> {
-    match format {
-        Some(format) => parse_time(timestamp, format),
-        None => match try_parse_time(timestamp) {
-            Some(timestamp) => Ok(timestamp),
-            None => Err(Error::new("couldn't guess time format")),
-        },
+fn missing_field_error(entry: &Entry) -> Error {
+    match entry {
+        Entry::Tuple(..) => Error::new("tuple entry index out of range"),
+        Entry::Dict(..) => Error::new("dict entry field not found"),
+    }
+}
+
+fn unmapped_entry_fields<'a>(
+    entry: &'a Entry,
+    mapping: &'a MappingRules,
+    wildcard: &'a Wildcard,
+) -> Box<dyn Iterator<Item = (FieldLoc, &'a String)> + 'a> {
+    let is_unmapped = move |loc: &FieldLoc| {
+        !wildcard.except.contains(loc)
+            && !mapping.fields.iter().any(|f| match f.typ {
+                FieldType::Const(_) | FieldType::Computed(_) => false,
+                _ => f.loc == *loc,
+            })
+    };
+
+    match entry {
+        Entry::Tuple(_, tuple) => Box::new(
+            tuple
+                .iter()
+                .enumerate()
+                .filter_map(|(pos, datum)| Some((FieldLoc::Position(pos), datum.as_ref()?)))
+                .filter(move |(loc, _)| is_unmapped(loc)),
+        ),
+        Entry::Dict(_, dict) => Box::new(
+            dict.iter()
+                .map(|(name, datum)| (FieldLoc::Name(name.clone()), datum))
+                .filter(move |(loc, _)| is_unmapped(loc)),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::program::parse_program;
+
+    #[test]
+    fn test_unmatched_regex_group() -> std::result::Result<(), Box<dyn std::error::Error>> {
+        let program = r#"/(\w+) ?(\d+)?/ | map {.0 as k, .1:num = -1}"#;
+        let decoding = RegexDecodingStrategy::new(r#"(\w+) ?(\d+)?"#)?;
+        let mapping = MappingStrategy::new(parse_program(program)?.mapper.unwrap());
+
+        #[rustfmt::skip]
+        let tests = [
+            ("a 1", "a", 1.0),
+            ("b", "b", -1.0),  // the group didn't participate in the match
+        ];
+        for (line, key, value) in tests.iter() {
+            let entry = match decoding.decode(line.as_bytes())? {
+                DecodingResult::Tuple(tuple) => Entry::Tuple(1, tuple),
+                DecodingResult::Dict(dict) => Entry::Dict(1, dict),
+            };
+            let record = mapping.map(entry)?;
+            assert_eq!(
+                Some(&key.to_string()),
+                record.labels().get("k"),
+                "line {}",
+                line
+            );
+            assert_eq!(Some(value), record.values().get("f1"), "line {}", line);
+        }
+        Ok(())
+    }
+    #[test]
+    fn test_empty_computed_label() -> std::result::Result<(), Box<dyn std::error::Error>> {
+        let program =
+            r#"/(\S+)/ | map {cidr_bucket(.0, 24) as net, cidr_bucket(.0, 16) = "none" as net16}"#;
+        let decoding = RegexDecodingStrategy::new(r#"(\S+)"#)?;
+        let mapping = MappingStrategy::new(parse_program(program)?.mapper.unwrap());
+
+        #[rustfmt::skip]
+        let tests = [
+            ("10.1.2.3", Some("10.1.2.0/24"), "10.1.0.0/16"),
+            ("foo", None, "none"),  // not an IP address
+        ];
+        for (line, net, net16) in tests.iter() {
+            let entry = match decoding.decode(line.as_bytes())? {
+                DecodingResult::Tuple(tuple) => Entry::Tuple(1, tuple),
+                DecodingResult::Dict(dict) => Entry::Dict(1, dict),
+            };
+            let record = mapping.map(entry)?;
+            assert_eq!(
+                net.map(str::to_owned).as_ref(),
+                record.labels().get("net"),
+                "line {}",
+                line
+            );
+            assert_eq!(
+                Some(&net16.to_string()),
+                record.labels().get("net16"),
+                "line {}",
+                line
+            );
+        }
+        Ok(())
     }
 }
@@ -1,5 +1,9 @@
 mod decoding;
 mod mapping;
+mod parallel;
+mod probe;
 
 pub use decoding::*;
 pub use mapping::*;
+pub use parallel::*;
+pub use probe::*;
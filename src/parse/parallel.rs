@@ -0,0 +1,211 @@
+use std::collections::BTreeMap;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+use super::decoding::{DecodingResult, DecodingStrategy, Entry};
+use super::mapping::{MappingStrategy, Record};
+use crate::error::Result;
+use crate::input::Line;
+
+const BATCH_SIZE: usize = 1024;
+
+type LineIter = Box<dyn std::iter::Iterator<Item = Result<Line>> + Send>;
+
+type LineBatch = (usize, Vec<Result<Line>>);
+
+type RecordBatch = (usize, Vec<Result<Record>>);
+
+/// Decodes and maps lines on a pool of worker threads.
+///
+/// A dedicated thread reads lines and cuts them into batches. Workers
+/// decode and map whole batches, and the resulting records are put back
+/// into the input order before being handed out. Hence, the output is
+/// exactly the same as of the single-threaded Decoder -> Mapper chain.
+///
+/// Since lines are read in batches, it's only suitable for inputs that
+/// don't stall, i.e. files rather than live streams.
+pub struct ParallelMapping {
+    results: mpsc::Receiver<RecordBatch>,
+    pending: BTreeMap<usize, Vec<Result<Record>>>,
+    current: std::vec::IntoIter<Result<Record>>,
+    next_batch: usize,
+}
+
+impl ParallelMapping {
+    pub fn new(
+        lines: LineIter,
+        decoding: Box<dyn DecodingStrategy>,
+        mapping: MappingStrategy,
+        workers: usize,
+    ) -> Self {
+        // Bounded queues keep the reader from running too far ahead of the workers.
+        let (batch_tx, batch_rx) = mpsc::sync_channel::<LineBatch>(2 * workers);
+        let (result_tx, result_rx) = mpsc::sync_channel::<RecordBatch>(2 * workers);
+
+        thread::spawn(move || read_batches(lines, batch_tx));
+
+        let batch_rx = Arc::new(Mutex::new(batch_rx));
+        let decoding: Arc<dyn DecodingStrategy> = Arc::from(decoding);
+        for _ in 0..workers {
+            let batch_rx = Arc::clone(&batch_rx);
+            let result_tx = result_tx.clone();
+            let decoding = Arc::clone(&decoding);
+            // Mapping is stateful (e.g. guessed time formats), and the state
+            // must not depend on which worker happens to map a line first.
+            let mapping = mapping.clone();
+
+            thread::spawn(move || loop {
+                let received = batch_rx.lock().expect("poisoned batch queue").recv();
+                let (seq_no, lines) = match received {
+                    Ok(batch) => batch,
+                    Err(_) => break, // input drained
+                };
+
+                let records = lines
+                    .into_iter()
+                    .map(|line| decode_and_map(line?, decoding.as_ref(), &mapping))
+                    .collect();
+
+                if result_tx.send((seq_no, records)).is_err() {
+                    break; // consumer is gone
+                }
+            });
+        }
+
+        Self {
+            results: result_rx,
+            pending: BTreeMap::new(),
+            current: Vec::new().into_iter(),
+            next_batch: 0,
+        }
+    }
+}
+
+impl std::iter::Iterator for ParallelMapping {
+    type Item = Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(record) = self.current.next() {
+                return Some(record);
+            }
+
+            while !self.pending.contains_key(&self.next_batch) {
+                match self.results.recv() {
+                    Ok((seq_no, records)) => {
+                        self.pending.insert(seq_no, records);
+                    }
+                    Err(_) => return None, // EOF
+                }
+            }
+
+            self.current = self
+                .pending
+                .remove(&self.next_batch)
+                .expect("batch must be pending")
+                .into_iter();
+            self.next_batch += 1;
+        }
+    }
+}
+
+fn read_batches(lines: LineIter, batch_tx: mpsc::SyncSender<LineBatch>) {
+    let mut seq_no = 0;
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+
+    for line in lines {
+        batch.push(line);
+        if batch.len() == BATCH_SIZE {
+            let full = std::mem::replace(&mut batch, Vec::with_capacity(BATCH_SIZE));
+            if batch_tx.send((seq_no, full)).is_err() {
+                return; // consumer is gone
+            }
+            seq_no += 1;
+        }
+    }
+
+    if !batch.is_empty() {
+        let _ = batch_tx.send((seq_no, batch));
+    }
+}
+
+// Mirrors Decoder::next() followed by MappingStrategy::map().
+fn decode_and_map(
+    (line_no, line, labels): Line,
+    decoding: &dyn DecodingStrategy,
+    mapping: &MappingStrategy,
+) -> Result<Record> {
+    let entry = match decoding.decode(&line) {
+        Ok(DecodingResult::Tuple(v)) => Entry::Tuple(line_no, v),
+        Ok(DecodingResult::Dict(v)) => Entry::Dict(line_no, v),
+        Err(e) => return Err(("line decoding failed", e).into()),
+    };
+    let mut record = mapping.map(entry)?;
+    record.add_source_labels(labels);
+    Ok(record)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::LineReader;
+    use crate::parse::{Decoder, RegexDecodingStrategy};
+    use crate::program::parse_program;
+
+    #[test]
+    fn test_same_as_sequential() -> std::result::Result<(), Box<dyn std::error::Error>> {
+        let mut input = Vec::new();
+        for i in 0..10_000 {
+            if i % 7 == 0 {
+                input.extend_from_slice(b"garbage\n");
+            } else if i % 3 == 0 {
+                // Makes the time format guessing switch formats back and forth.
+                input.extend_from_slice(
+                    format!("2021-01-01T00:{:02}:00 GET /{} {}\n", i % 60, i % 5, i).as_bytes(),
+                );
+            } else {
+                input.extend_from_slice(
+                    format!("{} GET /{} {}\n", 1609459200 + i / 10, i % 5, i).as_bytes(),
+                );
+            }
+        }
+
+        let program =
+            r#"/(\S+) (\w+) (\S+) (\d+)/ | map {.0:ts, .1 as method, .2 as path, .3:num as n}"#;
+        let regex = r#"(\S+) (\w+) (\S+) (\d+)"#;
+
+        let mapping = MappingStrategy::new(parse_program(program)?.mapper.unwrap());
+        let sequential = Decoder::new(
+            Box::new(LineReader::new(std::io::Cursor::new(input.clone()))),
+            Box::new(RegexDecodingStrategy::new(regex)?),
+        )
+        .map(move |entry| mapping.map(entry?.0));
+
+        let parallel = ParallelMapping::new(
+            Box::new(LineReader::new(std::io::Cursor::new(input))),
+            Box::new(RegexDecodingStrategy::new(regex)?),
+            MappingStrategy::new(parse_program(program)?.mapper.unwrap()),
+            4,
+        );
+
+        let mut count = 0;
+        for (expected, actual) in sequential.zip(parallel) {
+            match (expected, actual) {
+                (Ok(expected), Ok(actual)) => {
+                    assert_eq!(expected.line_no(), actual.line_no());
+                    assert_eq!(expected.timestamp(), actual.timestamp());
+                    assert_eq!(expected.labels(), actual.labels());
+                    assert_eq!(expected.values(), actual.values());
+                    assert_eq!(expected.buckets(), actual.buckets());
+                }
+                (Err(expected), Err(actual)) => {
+                    assert_eq!(expected.to_string(), actual.to_string());
+                }
+                (expected, actual) => panic!("expected {:?}, got {:?}", expected, actual),
+            }
+            count += 1;
+        }
+        assert_eq!(10_000, count);
+        Ok(())
+    }
+}
@@ -0,0 +1,25 @@
+use super::decoding::{DecodingResult, DecodingStrategy, Entry};
+use super::mapping::MappingStrategy;
+use crate::model::Timestamp;
+
+/// Extracts timestamps from raw lines one by one, i.e. without building
+/// the whole decoder -> mapper iterator chain. Handy for probing random
+/// positions of seekable inputs.
+pub struct TimestampProbe {
+    decoding: Box<dyn DecodingStrategy>,
+    mapping: MappingStrategy,
+}
+
+impl TimestampProbe {
+    pub fn new(decoding: Box<dyn DecodingStrategy>, mapping: MappingStrategy) -> Self {
+        Self { decoding, mapping }
+    }
+
+    pub fn timestamp(&self, line: &[u8]) -> Option<Timestamp> {
+        let entry = match self.decoding.decode(line).ok()? {
+            DecodingResult::Tuple(v) => Entry::Tuple(0, v),
+            DecodingResult::Dict(v) => Entry::Dict(0, v),
+        };
+        self.mapping.map(entry).ok()?.timestamp()
+    }
+}
@@ -0,0 +1,721 @@
+use nom::{
+    branch::alt,
+    bytes::complete::{tag, tag_no_case},
+    character::complete::{char, satisfy},
+    combinator::{map, not, peek, value},
+    sequence::{preceded, terminated},
+};
+
+use regex::Regex;
+
+use std::sync::{Arc, Mutex};
+
+use super::{mapper_field_loc, FieldLoc};
+use crate::utils::drain::{TemplateMiner, DEFAULT_SIMILARITY};
+use crate::utils::net::{Cidr, CidrTable};
+use crate::utils::parse::{
+    label_identifier, maybe_lpadded, number_literal, separated_list, string_literal, IResult,
+    ParseError, Span,
+};
+use crate::utils::route::RouteTable;
+
+/// Expression computing a record field from the entry fields,
+/// e.g. `.bytes_sent / .request_time` or `concat(.host, ":", .port)`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FieldExpr {
+    Field(FieldLoc),
+    Number(f64),
+    String(String),
+    Negation(Box<FieldExpr>),
+    Not(Box<FieldExpr>),
+    /// `=~` (unanchored, i.e. the regex may match any part of the string).
+    Match(Box<FieldExpr>, Pattern),
+    Binary(Box<FieldExpr>, FieldOp, Box<FieldExpr>),
+    Call(FieldFunc, Vec<FieldExpr>),
+    Transform(Box<FieldExpr>, Transform),
+    /// `in_cidr(.addr, "10.0.0.0/8", ...)` - whether the address belongs to any of the networks.
+    InCidr(Box<FieldExpr>, Vec<Cidr>),
+    /// `cidr_bucket(.addr, 24[, 48])` - the network (of the IPv4 or IPv6 prefix length) of the address.
+    CidrBucket(Box<FieldExpr>, u8, u8),
+    /// `cidr_lookup(.addr, "subnets.csv")` - the name of the most specific network of the address.
+    CidrLookup(Box<FieldExpr>, Arc<CidrTable>),
+    /// `template(.msg)` or `template_id(.msg)` - the template the message is mined into.
+    Template(Box<FieldExpr>, TemplatePart, SharedMiner),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TemplatePart {
+    /// The template string with wildcards (gets more general over time).
+    Pattern,
+    /// Stable ID of the template.
+    Id,
+}
+
+/// Mining is stateful. All the calls on the same message (and similarity)
+/// in a map expression share the miner, so that `template()` and
+/// `template_id()` agree on the template.
+#[derive(Clone)]
+pub struct SharedMiner(pub Arc<Mutex<TemplateMiner>>);
+
+impl PartialEq for SharedMiner {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl std::fmt::Debug for SharedMiner {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "SharedMiner(..)")
+    }
+}
+
+impl FieldExpr {
+    /// The entry field the expression is based on if it's a plain
+    /// field followed by some transformations, e.g. `.path | lower`.
+    pub fn transformed_field(&self) -> Option<&FieldLoc> {
+        match self {
+            FieldExpr::Field(loc) => Some(loc),
+            FieldExpr::Transform(expr, _) => expr.transformed_field(),
+            _ => None,
+        }
+    }
+
+    /// Whether the expression has `template()` or `template_id()` calls.
+    pub fn mines_templates(&self) -> bool {
+        match self {
+            FieldExpr::Field(_) | FieldExpr::Number(_) | FieldExpr::String(_) => false,
+            FieldExpr::Negation(expr)
+            | FieldExpr::Not(expr)
+            | FieldExpr::Match(expr, _)
+            | FieldExpr::Transform(expr, _)
+            | FieldExpr::InCidr(expr, _)
+            | FieldExpr::CidrBucket(expr, ..)
+            | FieldExpr::CidrLookup(expr, _) => expr.mines_templates(),
+            FieldExpr::Binary(lhs, _, rhs) => lhs.mines_templates() || rhs.mines_templates(),
+            FieldExpr::Call(_, args) => args.iter().any(|arg| arg.mines_templates()),
+            FieldExpr::Template(..) => true,
+        }
+    }
+
+    /// Calls `f` with the message expression and the miner of every
+    /// `template()` or `template_id()` call within the expression.
+    pub fn visit_templates(&mut self, f: &mut dyn FnMut(&FieldExpr, &mut SharedMiner)) {
+        match self {
+            FieldExpr::Field(_) | FieldExpr::Number(_) | FieldExpr::String(_) => (),
+            FieldExpr::Negation(expr)
+            | FieldExpr::Not(expr)
+            | FieldExpr::Match(expr, _)
+            | FieldExpr::Transform(expr, _)
+            | FieldExpr::InCidr(expr, _)
+            | FieldExpr::CidrBucket(expr, ..)
+            | FieldExpr::CidrLookup(expr, _) => expr.visit_templates(f),
+            FieldExpr::Binary(lhs, _, rhs) => {
+                lhs.visit_templates(f);
+                rhs.visit_templates(f);
+            }
+            FieldExpr::Call(_, args) => args.iter_mut().for_each(|arg| arg.visit_templates(f)),
+            FieldExpr::Template(expr, _, miner) => {
+                expr.visit_templates(f);
+                f(expr, miner);
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FieldOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Pow,
+    Eql,
+    Neq,
+    Gtr,
+    Gte,
+    Lss,
+    Lte,
+    And,
+    Or,
+}
+
+// Same as of the comparison operators.
+const MATCH_PRECEDENCE: usize = 30;
+
+impl FieldOp {
+    fn precedence(self) -> usize {
+        use FieldOp::*;
+
+        match self {
+            Or => 10,
+            And => 20,
+            Eql | Neq | Gtr | Gte | Lss | Lte => MATCH_PRECEDENCE,
+            Add | Sub => 40,
+            Mul | Div | Mod => 50,
+            Pow => 60,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FieldFunc {
+    Concat,
+}
+
+/// String transformation applied like `.path | url_path | lower`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Transform {
+    Lower,
+    Upper,
+    Trim,
+    /// Character-based, the start is 0-based.
+    Substr(usize, Option<usize>),
+    RegexReplace(Pattern, String),
+    /// The part number is 1-based (as in SQL).
+    SplitPart(String, usize),
+    UrlPath,
+    UrlQuery(String),
+    Hash,
+    /// Keyed hash, the key is read from an environment variable at parse time.
+    Hmac(SecretKey),
+    /// IPv4 and IPv6 prefix lengths.
+    IpTruncate(u8, u8),
+    MaskEmail,
+    Scrub(Pattern),
+    /// URL path to route, e.g. /users/123 -> /users/:id.
+    Route(RouteTable),
+}
+
+/// Environment variable `hmac` reads the key from by default.
+pub const HMAC_KEY_ENV: &str = "PQ_HMAC_KEY";
+
+/// Doesn't show up in debug output.
+#[derive(Clone, PartialEq)]
+pub struct SecretKey(pub Vec<u8>);
+
+impl std::fmt::Debug for SecretKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "SecretKey(..)")
+    }
+}
+
+/// Replaces whatever `scrub` matches.
+pub const SCRUBBED: &str = "[REDACTED]";
+
+/// Compiled at parse time to not recompile it on every line.
+#[derive(Clone, Debug)]
+pub struct Pattern(pub Regex);
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+#[derive(Clone, Debug)]
+enum TransformArg {
+    Number(f64),
+    String(String),
+}
+
+/// Parses an expression with binary operators binding tighter than `min_prec`.
+pub(super) fn field_expr<'a>(min_prec: usize) -> impl FnMut(Span<'a>) -> IResult<FieldExpr> {
+    move |input: Span| {
+        let (mut rest, mut lhs) = alt((
+            // Order matters here! E.g., '.1' is a field, not a number.
+            map(preceded(char('.'), mapper_field_loc), FieldExpr::Field),
+            field_expr_not,
+            field_expr_call,
+            field_expr_paren,
+            map(string_literal, FieldExpr::String),
+            map(number_literal, FieldExpr::Number),
+            field_expr_negation,
+        ))(input)?;
+
+        // Unlike in queries, a non-operator simply ends the expression
+        // since it can be followed by an alias, a comma, etc.
+        loop {
+            if let Ok((tmp_rest, negated)) = maybe_lpadded(match_op)(rest) {
+                if MATCH_PRECEDENCE <= min_prec {
+                    break;
+                }
+
+                let (tmp_rest, regex) = match maybe_lpadded(string_literal)(tmp_rest) {
+                    Ok((r, regex)) => (r, regex),
+                    Err(nom::Err::Error(_)) => {
+                        return Err(nom::Err::Failure(ParseError::partial(
+                            "field expression",
+                            "regex string literal",
+                            tmp_rest,
+                        )))
+                    }
+                    Err(e) => return Err(e),
+                };
+                let pattern = match Regex::new(&regex) {
+                    Ok(regex) => Pattern(regex),
+                    Err(e) => {
+                        return Err(nom::Err::Failure(ParseError::new(
+                            format!("bad regex: {}", e),
+                            tmp_rest,
+                        )))
+                    }
+                };
+
+                rest = tmp_rest;
+                lhs = FieldExpr::Match(Box::new(lhs), pattern);
+                if negated {
+                    lhs = FieldExpr::Not(Box::new(lhs));
+                }
+                continue;
+            }
+
+            let (tmp_rest, op) = match maybe_lpadded(field_op)(rest) {
+                Ok((r, op)) => (r, op),
+                Err(_) => break,
+            };
+            if op.precedence() <= min_prec {
+                break;
+            }
+
+            // '^' is right-associative.
+            let rhs_prec = match op {
+                FieldOp::Pow => op.precedence() - 1,
+                _ => op.precedence(),
+            };
+
+            let (tmp_rest, rhs) = match maybe_lpadded(field_expr(rhs_prec))(tmp_rest) {
+                Ok((r, e)) => (r, e),
+                Err(nom::Err::Error(_)) => {
+                    return Err(nom::Err::Failure(ParseError::partial(
+                        "field expression",
+                        "right-hand expression",
+                        tmp_rest,
+                    )))
+                }
+                Err(e) => return Err(e),
+            };
+
+            rest = tmp_rest;
+            lhs = FieldExpr::Binary(Box::new(lhs), op, Box::new(rhs));
+        }
+
+        Ok((rest, lhs))
+    }
+}
+
+/// Parses an expression followed by zero or more `| <transform>`.
+pub(super) fn field_pipeline(input: Span) -> IResult<FieldExpr> {
+    let (mut rest, mut expr) = field_expr(0)(input)?;
+
+    while let Ok((tmp_rest, _)) = maybe_lpadded(char('|'))(rest) {
+        let (tmp_rest, transform) = match maybe_lpadded(transform)(tmp_rest) {
+            Ok((r, t)) => (r, t),
+            Err(nom::Err::Error(_)) => {
+                return Err(nom::Err::Failure(ParseError::partial(
+                    "field expression",
+                    "transformation (lower, upper, trim, substr, regex_replace, split_part, url_path, url_query, hash, hmac, ip_truncate, mask_email, scrub, or route)",
+                    tmp_rest,
+                )))
+            }
+            Err(e) => return Err(e),
+        };
+        rest = tmp_rest;
+        expr = FieldExpr::Transform(Box::new(expr), transform);
+    }
+
+    Ok((rest, expr))
+}
+
+fn transform(input: Span) -> IResult<Transform> {
+    let (rest, name) = label_identifier(input)?;
+
+    let (rest, args) = match maybe_lpadded(separated_list(
+        '(',
+        ')',
+        ',',
+        transform_arg,
+        "transformation",
+        "comma-separated list of string or number literals or ')'",
+    ))(rest)
+    {
+        Ok((rest, args)) => (rest, args),
+        Err(nom::Err::Error(_)) => (rest, vec![]), // parentheses are optional
+        Err(e) => return Err(e),
+    };
+
+    use TransformArg::*;
+    let transform = match (name.as_str(), args.as_slice()) {
+        ("lower", []) => Transform::Lower,
+        ("upper", []) => Transform::Upper,
+        ("trim", []) => Transform::Trim,
+        ("substr", [Number(start)]) if *start >= 0.0 => Transform::Substr(*start as usize, None),
+        ("substr", [Number(start), Number(len)]) if *start >= 0.0 && *len >= 0.0 => {
+            Transform::Substr(*start as usize, Some(*len as usize))
+        }
+        ("regex_replace", [String(regex), String(replacement)]) => match Regex::new(regex) {
+            Ok(regex) => Transform::RegexReplace(Pattern(regex), replacement.clone()),
+            Err(e) => {
+                return Err(nom::Err::Failure(ParseError::new(
+                    format!("bad regex_replace() pattern: {}", e),
+                    input,
+                )))
+            }
+        },
+        ("split_part", [String(sep), Number(n)]) if *n >= 1.0 => {
+            Transform::SplitPart(sep.clone(), *n as usize)
+        }
+        ("url_path", []) => Transform::UrlPath,
+        ("url_query", [String(key)]) => Transform::UrlQuery(key.clone()),
+        ("hash", []) => Transform::Hash,
+        ("hmac", []) => hmac_transform(HMAC_KEY_ENV, input)?,
+        ("hmac", [String(env)]) => hmac_transform(env, input)?,
+        ("ip_truncate", []) => Transform::IpTruncate(24, 48),
+        ("ip_truncate", [Number(v4)]) if is_prefix_len(*v4, 32) => {
+            Transform::IpTruncate(*v4 as u8, 48)
+        }
+        ("ip_truncate", [Number(v4), Number(v6)])
+            if is_prefix_len(*v4, 32) && is_prefix_len(*v6, 128) =>
+        {
+            Transform::IpTruncate(*v4 as u8, *v6 as u8)
+        }
+        ("mask_email", []) => Transform::MaskEmail,
+        ("scrub", [String(regex)]) => match Regex::new(regex) {
+            Ok(regex) => Transform::Scrub(Pattern(regex)),
+            Err(e) => {
+                return Err(nom::Err::Failure(ParseError::new(
+                    format!("bad scrub() pattern: {}", e),
+                    input,
+                )))
+            }
+        },
+        ("route", templates) => {
+            let templates: Option<Vec<_>> = templates
+                .iter()
+                .map(|t| match t {
+                    String(t) => Some(t.clone()),
+                    _ => None,
+                })
+                .collect();
+            match templates.map(|t| RouteTable::new(&t)) {
+                Some(Ok(table)) => Transform::Route(table),
+                Some(Err(e)) => {
+                    return Err(nom::Err::Failure(ParseError::new(e.to_string(), input)))
+                }
+                None => return Err(bad_args(r#"route(["template" | "@file", ...])"#, input)),
+            }
+        }
+        ("lower", _)
+        | ("upper", _)
+        | ("trim", _)
+        | ("url_path", _)
+        | ("hash", _)
+        | ("mask_email", _) => {
+            return Err(nom::Err::Failure(ParseError::new(
+                format!("{}() takes no arguments", name),
+                input,
+            )))
+        }
+        ("substr", _) => return Err(bad_args("substr(start[, length])", input)),
+        ("regex_replace", _) => {
+            return Err(bad_args(r#"regex_replace("regex", "replacement")"#, input))
+        }
+        ("split_part", _) => return Err(bad_args(r#"split_part("separator", n)"#, input)),
+        ("url_query", _) => return Err(bad_args(r#"url_query("key")"#, input)),
+        ("hmac", _) => return Err(bad_args(r#"hmac(["KEY_ENV_VARIABLE"])"#, input)),
+        ("ip_truncate", _) => return Err(bad_args("ip_truncate([v4_prefix[, v6_prefix]])", input)),
+        ("scrub", _) => return Err(bad_args(r#"scrub("regex")"#, input)),
+        _ => {
+            return Err(nom::Err::Error(ParseError::new(
+                format!("unknown transformation '{}'", name),
+                input,
+            )))
+        }
+    };
+
+    Ok((rest, transform))
+}
+
+fn hmac_transform<'a>(env: &str, input: Span<'a>) -> Result<Transform, nom::Err<ParseError<'a>>> {
+    match std::env::var(env) {
+        Ok(key) if !key.is_empty() => Ok(Transform::Hmac(SecretKey(key.into_bytes()))),
+        _ => Err(nom::Err::Failure(ParseError::new(
+            format!(
+                "hmac() key is missing, set the {} environment variable",
+                env
+            ),
+            input,
+        ))),
+    }
+}
+
+fn is_prefix_len(n: f64, max: u8) -> bool {
+    n >= 0.0 && n <= max as f64 && n.fract() == 0.0
+}
+
+fn transform_arg(input: Span) -> IResult<TransformArg> {
+    alt((
+        map(string_literal, TransformArg::String),
+        map(number_literal, TransformArg::Number),
+    ))(input)
+}
+
+fn bad_args<'a>(usage: &str, input: Span<'a>) -> nom::Err<ParseError<'a>> {
+    nom::Err::Failure(ParseError::new(
+        format!("bad arguments, expected {}", usage),
+        input,
+    ))
+}
+
+fn field_op(input: Span) -> IResult<FieldOp> {
+    alt((
+        value(FieldOp::Add, tag("+")),
+        value(FieldOp::Sub, tag("-")),
+        value(FieldOp::Mul, tag("*")),
+        value(FieldOp::Div, tag("/")),
+        value(FieldOp::Mod, tag("%")),
+        value(FieldOp::Pow, tag("^")),
+        value(FieldOp::Eql, tag("==")),
+        value(FieldOp::Neq, tag("!=")),
+        value(FieldOp::Gte, tag(">=")),
+        value(FieldOp::Gtr, tag(">")),
+        value(FieldOp::Lte, tag("<=")),
+        value(FieldOp::Lss, tag("<")),
+        value(FieldOp::And, keyword("and")),
+        value(FieldOp::Or, keyword("or")),
+    ))(input)
+}
+
+// Returns true for '!~'.
+fn match_op(input: Span) -> IResult<bool> {
+    alt((value(false, tag("=~")), value(true, tag("!~"))))(input)
+}
+
+fn keyword<'a>(kw: &'static str) -> impl FnMut(Span<'a>) -> IResult<Span<'a>> {
+    terminated(
+        tag_no_case(kw),
+        not(peek(satisfy(|c| c.is_alphanumeric() || c == '_'))),
+    )
+}
+
+fn field_expr_not(input: Span) -> IResult<FieldExpr> {
+    let (rest, _) = keyword("not")(input)?;
+    let (rest, expr) = match maybe_lpadded(field_expr(FieldOp::And.precedence()))(rest) {
+        Ok((rest, expr)) => (rest, expr),
+        Err(nom::Err::Error(_)) => {
+            return Err(nom::Err::Failure(ParseError::partial(
+                "field expression",
+                "expression after 'not'",
+                rest,
+            )))
+        }
+        Err(e) => return Err(e),
+    };
+    Ok((rest, FieldExpr::Not(Box::new(expr))))
+}
+
+fn field_expr_negation(input: Span) -> IResult<FieldExpr> {
+    let (rest, _) = char('-')(input)?;
+    let (rest, expr) = maybe_lpadded(field_expr(FieldOp::Mul.precedence()))(rest)?;
+    Ok((rest, FieldExpr::Negation(Box::new(expr))))
+}
+
+fn field_expr_paren(input: Span) -> IResult<FieldExpr> {
+    let (rest, _) = char('(')(input)?;
+
+    let (rest, expr) = match maybe_lpadded(field_pipeline)(rest) {
+        Ok((rest, expr)) => (rest, expr),
+        Err(nom::Err::Error(_)) => {
+            return Err(nom::Err::Failure(ParseError::partial(
+                "parentheses",
+                "valid expression",
+                rest,
+            )))
+        }
+        Err(e) => return Err(e),
+    };
+
+    match maybe_lpadded(char(')'))(rest) {
+        Ok((rest, _)) => Ok((rest, expr)),
+        Err(nom::Err::Error(_)) => Err(nom::Err::Failure(ParseError::partial(
+            "parentheses",
+            ")",
+            rest,
+        ))),
+        Err(e) => Err(e),
+    }
+}
+
+fn field_expr_call(input: Span) -> IResult<FieldExpr> {
+    let (rest, name) = label_identifier(input)?;
+    let known = [
+        "concat",
+        "in_cidr",
+        "cidr_bucket",
+        "cidr_lookup",
+        "template",
+        "template_id",
+    ];
+    if !known.contains(&name.as_str()) {
+        return Err(nom::Err::Error(ParseError::new(
+            format!("unknown function '{}'", name),
+            input,
+        )));
+    }
+
+    let (rest, mut args) = maybe_lpadded(separated_list(
+        '(',
+        ')',
+        ',',
+        field_pipeline,
+        "function call",
+        "comma-separated list of arguments or ')'",
+    ))(rest)?;
+
+    if name == "concat" {
+        return Ok((rest, FieldExpr::Call(FieldFunc::Concat, args)));
+    }
+
+    use FieldExpr::{Number, String};
+
+    // The rest of the functions take an expression followed by literals.
+    if args.is_empty() {
+        return Err(bad_args(&func_usage(&name), input));
+    }
+    let addr = Box::new(args.remove(0));
+
+    if name == "template" || name == "template_id" {
+        let part = match name.as_str() {
+            "template" => TemplatePart::Pattern,
+            _ => TemplatePart::Id,
+        };
+        let similarity = match args.as_slice() {
+            [] => DEFAULT_SIMILARITY,
+            [Number(sim)] if *sim > 0.0 && *sim <= 1.0 => *sim,
+            _ => return Err(bad_args(&func_usage(&name), input)),
+        };
+        let miner = SharedMiner(Arc::new(Mutex::new(TemplateMiner::new(similarity))));
+        return Ok((rest, FieldExpr::Template(addr, part, miner)));
+    }
+
+    let expr = match (name.as_str(), args.as_slice()) {
+        ("in_cidr", cidrs) if !cidrs.is_empty() => {
+            let mut parsed = vec![];
+            for cidr in cidrs {
+                match cidr {
+                    String(cidr) => match cidr.parse::<Cidr>() {
+                        Ok(cidr) => parsed.push(cidr),
+                        Err(e) => {
+                            return Err(nom::Err::Failure(ParseError::new(e.to_string(), input)))
+                        }
+                    },
+                    _ => return Err(bad_args(&func_usage(&name), input)),
+                }
+            }
+            FieldExpr::InCidr(addr, parsed)
+        }
+        ("cidr_bucket", [Number(v4)]) if is_prefix_len(*v4, 32) => {
+            FieldExpr::CidrBucket(addr, *v4 as u8, 48)
+        }
+        ("cidr_bucket", [Number(v4), Number(v6)])
+            if is_prefix_len(*v4, 32) && is_prefix_len(*v6, 128) =>
+        {
+            FieldExpr::CidrBucket(addr, *v4 as u8, *v6 as u8)
+        }
+        ("cidr_lookup", [String(path)]) => match CidrTable::from_csv(path) {
+            Ok(table) => FieldExpr::CidrLookup(addr, Arc::new(table)),
+            Err(e) => return Err(nom::Err::Failure(ParseError::new(e.to_string(), input))),
+        },
+        _ => return Err(bad_args(&func_usage(&name), input)),
+    };
+    Ok((rest, expr))
+}
+
+fn func_usage(name: &str) -> std::string::String {
+    match name {
+        "in_cidr" => r#"in_cidr(address, "cidr"[, "cidr", ...])"#,
+        "cidr_bucket" => "cidr_bucket(address, v4_prefix[, v6_prefix])",
+        "template" | "template_id" => "template(message[, similarity]), similarity is in (0, 1]",
+        _ => r#"cidr_lookup(address, "file.csv")"#,
+    }
+    .to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(name: &str) -> Box<FieldExpr> {
+        Box::new(FieldExpr::Field(FieldLoc::Name(name.to_owned())))
+    }
+
+    #[test]
+    fn test_field_expr() -> std::result::Result<(), nom::Err<ParseError<'static>>> {
+        use FieldExpr::*;
+
+        #[rustfmt::skip]
+        let tests = [
+            (".0", Field(FieldLoc::Position(0))),
+            ("1.5", Number(1.5)),
+            (".a / 1000", Binary(field("a"), FieldOp::Div, Box::new(Number(1000.0)))),
+            (
+                ".a + .b * .c",
+                Binary(field("a"), FieldOp::Add, Box::new(Binary(field("b"), FieldOp::Mul, field("c")))),
+            ),
+            (
+                "(.a + .b) * .c",
+                Binary(Box::new(Binary(field("a"), FieldOp::Add, field("b"))), FieldOp::Mul, field("c")),
+            ),
+            (
+                ".a - .b - .c",
+                Binary(Box::new(Binary(field("a"), FieldOp::Sub, field("b"))), FieldOp::Sub, field("c")),
+            ),
+            (
+                ".a ^ .b ^ .c",
+                Binary(field("a"), FieldOp::Pow, Box::new(Binary(field("b"), FieldOp::Pow, field("c")))),
+            ),
+            (
+                "-.a >= 1",
+                Binary(Box::new(Negation(field("a"))), FieldOp::Gte, Box::new(Number(1.0))),
+            ),
+            (
+                r#"concat(.host, ":", .port)"#,
+                Call(FieldFunc::Concat, vec![*field("host"), String(":".to_owned()), *field("port")]),
+            ),
+            (
+                r#".status >= 500 and .path !~ "^/health" or not .ok"#,
+                Binary(
+                    Box::new(Binary(
+                        Box::new(Binary(field("status"), FieldOp::Gte, Box::new(Number(500.0)))),
+                        FieldOp::And,
+                        Box::new(Not(Box::new(Match(field("path"), Pattern(Regex::new("^/health").unwrap()))))),
+                    )),
+                    FieldOp::Or,
+                    Box::new(Not(field("ok"))),
+                ),
+            ),
+            (".ua | lower", Transform(field("ua"), self::Transform::Lower)),
+            (
+                ".url | url_path() | split_part(\"/\", 2)",
+                Transform(
+                    Box::new(Transform(field("url"), self::Transform::UrlPath)),
+                    self::Transform::SplitPart("/".to_owned(), 2),
+                ),
+            ),
+            (
+                "concat(.a | upper, (.b | substr(1, 2)))",
+                Call(FieldFunc::Concat, vec![
+                    Transform(field("a"), self::Transform::Upper),
+                    Transform(field("b"), self::Transform::Substr(1, Some(2))),
+                ]),
+            ),
+        ];
+
+        for (input, expected) in &tests {
+            let (rest, actual) = field_pipeline(Span::new(input))?;
+            assert_eq!(0, rest.len(), "unparsed input while parsing {}", input);
+            assert_eq!(*expected, actual, "while parsing {}", input);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,1370 @@
+mod expr;
+
+use std::collections::HashSet;
+use std::time::Duration;
+
+use crate::error::{Error, Result};
+use crate::query::parser::{ast::Expr as QueryExpr, expr::expr as query_expr};
+use crate::utils::parse::{
+    duration, label_identifier, maybe_lpadded, number_literal, separated_list, string_literal,
+    IResult, ParseError, Span,
+};
+use crate::utils::time::{parse_timezone, Timezone};
+
+pub use expr::*;
+
+use nom::{
+    branch::alt,
+    bytes::complete::{tag_no_case, take},
+    character::complete::{char, digit1},
+    combinator::{map, recognize, value},
+    multi::separated_list1,
+    sequence::preceded,
+};
+
+// TODO: make all attributes private, add constructors with validation logic, and proper getters.
+
+#[derive(Debug)]
+pub struct AST {
+    pub decoder: Decoder,
+    pub redactor: Option<Redactor>,
+    pub mapper: Option<Mapper>,
+    pub filter: Option<FieldExpr>,
+    pub dedup: Option<Dedup>,
+    pub query: Option<QueryExpr>,
+    pub formatter: Option<Formatter>,
+}
+
+#[derive(Debug)]
+pub enum Decoder {
+    JSON,
+    Regex {
+        regex: String,
+    },
+    /// `switch { /.../ => map {...}, /.../ => map {...} }` - the first
+    /// matching arm decodes and maps the line.
+    Switch(Vec<SwitchArm>),
+    // TODO:
+    // CSV {
+    //     header: Vec<String>,
+    //     separator: String,
+    // },
+    // logfmt
+    // scanf
+    // Prometheus,
+    // InfluxDB,
+    // Nginx,
+    // Nginx:combined,
+    // Apache,
+    // Envoy,
+    // Redis
+}
+
+#[derive(Debug)]
+pub struct SwitchArm {
+    pub regex: String,
+    pub mapper: Mapper,
+}
+
+/// `redact { .client_ip | ip_truncate, .email | mask_email }` - rewrites
+/// the decoded fields, so no later stage (nor the output) sees the raw values.
+#[derive(Debug)]
+pub struct Redactor {
+    pub fields: Vec<RedactedField>,
+}
+
+#[derive(Debug)]
+pub struct RedactedField {
+    pub loc: FieldLoc,
+    pub transforms: Vec<Transform>,
+}
+
+/// Mapping a field to `__name__` and another one to `__value__` turns
+/// records into samples of the named metric (rather than of a metric
+/// per numeric field).
+pub const NAME_FIELD: &str = "__name__";
+pub const VALUE_FIELD: &str = "__value__";
+
+#[derive(Clone, Debug)]
+pub struct Mapper {
+    pub fields: Vec<MapperField>,
+    pub wildcard: Option<Wildcard>,
+    /// Timezone of timestamps without a UTC offset (unless the field has its own `tz`).
+    pub timezone: Option<Timezone>,
+    /// Year of timestamps lacking one (the current one by default).
+    pub year: Option<i32>,
+}
+
+impl Mapper {
+    /// Makes timestamp fields without an explicit tz use the given timezone.
+    pub fn with_default_timezone(mut self, tz: Timezone) -> Self {
+        self.timezone = Some(tz);
+        self
+    }
+
+    /// Makes timestamps lacking the year (e.g. of BSD syslog) use the given one.
+    pub fn with_default_year(mut self, year: i32) -> Self {
+        self.year = Some(year);
+        self
+    }
+
+    /// Whether there is an explicit (:ts) timestamp field.
+    pub fn has_timestamp(&self) -> bool {
+        self.fields
+            .iter()
+            .any(|f| matches!(f.typ, FieldType::Timestamp(..)))
+    }
+
+    /// Whether there are `template()` or `template_id()` calls. Mined
+    /// templates depend on the order of lines.
+    pub fn mines_templates(&self) -> bool {
+        self.fields.iter().any(|f| match f.typ {
+            FieldType::Computed(ref expr) => expr.mines_templates(),
+            _ => false,
+        })
+    }
+
+    fn visit_templates(&mut self, f: &mut dyn FnMut(&FieldExpr, &mut SharedMiner)) {
+        for field in self.fields.iter_mut() {
+            if let FieldType::Computed(ref mut expr) = field.typ {
+                expr.visit_templates(f);
+            }
+        }
+    }
+
+    // Makes the calls on the same message and similarity use the same miner.
+    fn share_template_miners(&mut self) {
+        let mut miners: Vec<(FieldExpr, f64, SharedMiner)> = Vec::new();
+        self.visit_templates(&mut |message, miner| {
+            let similarity = miner.0.lock().unwrap().similarity();
+            match miners
+                .iter()
+                .find(|(m, s, _)| m == message && *s == similarity)
+            {
+                Some((_, _, shared)) => *miner = shared.clone(),
+                None => miners.push((message.clone(), similarity, miner.clone())),
+            }
+        });
+    }
+}
+
+/// `*` or `* except .foo, .bar` - maps all the fields not mapped explicitly
+/// (and not excluded) using the FieldType::Auto rules.
+#[derive(Clone, Debug)]
+pub struct Wildcard {
+    pub except: Vec<FieldLoc>,
+}
+
+enum MapperItem {
+    Field(MapperField),
+    Wildcard(Wildcard),
+}
+
+#[derive(Clone, Debug)]
+pub struct MapperField {
+    pub loc: FieldLoc,
+    pub typ: FieldType,
+    pub alias: Option<String>,
+    /// Missing optional fields are omitted instead of invalidating the record.
+    pub optional: bool,
+    /// Used in place of the missing field (or of an empty computed label).
+    pub default: Option<String>,
+    pub histogram: Option<Histogram>,
+}
+
+/// Turns a numeric field into Prometheus-style cumulative bucket counters
+/// (`<name>_bucket{le="..."}`, `<name>_sum`, and `<name>_count`).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Histogram {
+    /// Sorted upper bounds, +Inf is implicit.
+    pub bounds: Vec<f64>,
+}
+
+enum HistogramArg {
+    Bound(f64),
+    Generated(Vec<f64>),
+}
+
+impl MapperField {
+    pub fn end_name(&self) -> String {
+        if let Some(ref alias) = self.alias {
+            return alias.clone();
+        }
+
+        if let FieldLoc::Name(ref name) = self.loc {
+            return name.clone();
+        }
+
+        if let FieldLoc::Position(pos) = self.loc {
+            return format!("f{}", pos);
+        }
+
+        panic!("malformed field definition");
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum FieldLoc {
+    Name(String),
+    Position(usize),
+}
+
+#[derive(Clone, Debug)]
+pub enum FieldType {
+    Auto,
+    Number,
+    String,
+    /// Normalized to seconds.
+    Duration,
+    /// Normalized to bytes.
+    Bytes,
+    /// A label holding an IP address in the canonical form (ports are dropped).
+    Ip,
+    Const(String),
+    Computed(FieldExpr),
+    /// Optional format and timezone of local times.
+    Timestamp(Option<String>, Option<Timezone>),
+}
+
+/// `dedup [by (.request_id, ...)] [within 5m]` - drops records repeating
+/// the key fields (or the whole record) of a record seen within the window.
+#[derive(Debug)]
+pub struct Dedup {
+    /// Record fields making the key. Empty means the whole record
+    /// (labels and values).
+    pub fields: Vec<String>,
+    pub window: Duration,
+}
+
+pub const DEFAULT_DEDUP_WINDOW: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Debug)]
+pub enum Formatter {
+    HumanReadable,
+    JSON,
+    PromAPI,
+    // TODO:
+    // PromQL,
+}
+
+pub fn parse_program(program: &str) -> Result<AST> {
+    match do_parse_program(Span::new(program.trim())) {
+        Ok((rest, _)) if rest.len() > 0 => Err(Error::from(
+            ParseError::partial("program", "EOF", rest).message(),
+        )),
+        Ok((_, ast)) => Ok(ast),
+        Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => Err(Error::from(e.message())),
+        Err(nom::Err::Incomplete(_)) => unreachable!(),
+    }
+}
+
+fn do_parse_program(input: Span) -> IResult<AST> {
+    // Parse decoder - the only one mandatory part of the program.
+    let (rest, decoder) = match decoder(input) {
+        Ok((rest, decoder)) => (rest, decoder),
+        Err(nom::Err::Error(_)) => return Err(nom::Err::Failure(ParseError::new(
+            "a valid pq program must start from a known parser (supported parsers: regex /.../, json, switch {...})"
+                .to_owned(),
+            input,
+        ))),
+        Err(e) => return Err(e),
+    };
+
+    if let Decoder::Switch(_) = decoder {
+        // Every arm has its own mapper already.
+        if let Ok((_, _)) = maybe_lpadded(preceded(
+            char::<Span, ParseError>('|'),
+            maybe_lpadded(alt((tag_no_case("redact"), tag_no_case("map")))),
+        ))(rest)
+        {
+            return Err(nom::Err::Failure(ParseError::new(
+                "switch cannot be followed by redact or map (map lines in the switch arms)"
+                    .to_owned(),
+                rest,
+            )));
+        }
+    }
+
+    let (rest, redactor) = match maybe_lpadded(preceded(char('|'), maybe_lpadded(redactor)))(rest) {
+        Ok((rest, redactor)) => (rest, Some(redactor)),
+        Err(nom::Err::Error(_)) => (rest, None),
+        Err(e) => return Err(e),
+    };
+
+    let (rest, mapper) = match maybe_lpadded(preceded(char('|'), maybe_lpadded(mapper)))(rest) {
+        Ok((rest, mapper)) => (rest, Some(mapper)),
+        Err(nom::Err::Error(_)) => (rest, None),
+        Err(e) => return Err(e),
+    };
+
+    let (rest, filter) = match maybe_lpadded(preceded(char('|'), maybe_lpadded(filter)))(rest) {
+        Ok((rest, filter)) => (rest, Some(filter)),
+        Err(nom::Err::Error(_)) => (rest, None),
+        Err(e) => return Err(e),
+    };
+
+    let (rest, dedup) = match maybe_lpadded(preceded(char('|'), maybe_lpadded(dedup)))(rest) {
+        Ok((rest, dedup)) => (rest, Some(dedup)),
+        Err(nom::Err::Error(_)) => (rest, None),
+        Err(e) => return Err(e),
+    };
+
+    let (rest, query) = match maybe_lpadded(preceded(char('|'), maybe_lpadded(query)))(rest) {
+        Ok((rest, query)) => (rest, Some(query)),
+        Err(nom::Err::Error(_)) => (rest, None),
+        Err(e) => return Err(e),
+    };
+
+    let (rest, formatter) = match maybe_lpadded(preceded(char('|'), maybe_lpadded(formatter)))(rest)
+    {
+        Ok((rest, formatter)) => (rest, Some(formatter)),
+        Err(nom::Err::Error(_)) => (rest, None),
+        Err(e) => return Err(e),
+    };
+
+    Ok((
+        rest,
+        AST {
+            decoder,
+            redactor,
+            mapper,
+            filter,
+            dedup,
+            query,
+            formatter,
+        },
+    ))
+}
+
+fn decoder(input: Span) -> IResult<Decoder> {
+    let (rest, decoder) = alt((
+        decoder_switch,
+        decoder_regex,
+        map(tag_no_case("json"), |_| Decoder::JSON),
+    ))(input)?;
+    Ok((rest, decoder))
+}
+
+// switch { /GET (\S+)/ => map {.0 as path}, /ERROR (.+)/ => map {.0 as error} }
+fn decoder_switch(input: Span) -> IResult<Decoder> {
+    let (rest, _) = alt((tag_no_case("switch"), tag_no_case("case")))(input)?;
+    let (rest, arms) = match maybe_lpadded(separated_list(
+        '{',
+        '}',
+        ',',
+        switch_arm,
+        "switch expression",
+        "switch arm (example: '/(\\d+)/ => map {.0:num as n}') or '}'",
+    ))(rest)
+    {
+        Ok((rest, arms)) => (rest, arms),
+        Err(nom::Err::Error(_)) => {
+            return Err(nom::Err::Failure(ParseError::partial(
+                "switch expression",
+                "comma-separated list of arms",
+                rest,
+            )))
+        }
+        Err(e) => return Err(e),
+    };
+
+    if arms.is_empty() {
+        return Err(nom::Err::Failure(ParseError::new(
+            "switch expects at least one arm".to_owned(),
+            rest,
+        )));
+    }
+
+    Ok((rest, Decoder::Switch(arms)))
+}
+
+// /ERROR (.+)/ => map {.0 as error}
+fn switch_arm(input: Span) -> IResult<SwitchArm> {
+    let (rest, regex) = match decoder_regex(input)? {
+        (rest, Decoder::Regex { regex }) => (rest, regex),
+        _ => unreachable!(),
+    };
+
+    let (rest, _) = match maybe_lpadded(tag_no_case("=>"))(rest) {
+        Ok((rest, arrow)) => (rest, arrow),
+        Err(nom::Err::Error(_)) => {
+            return Err(nom::Err::Failure(ParseError::partial(
+                "switch arm",
+                "=>",
+                rest,
+            )))
+        }
+        Err(e) => return Err(e),
+    };
+
+    let (rest, mapper) = match maybe_lpadded(mapper)(rest) {
+        Ok((rest, mapper)) => (rest, mapper),
+        Err(nom::Err::Error(_)) => {
+            return Err(nom::Err::Failure(ParseError::partial(
+                "switch arm",
+                "map expression",
+                rest,
+            )))
+        }
+        Err(e) => return Err(e),
+    };
+
+    Ok((rest, SwitchArm { regex, mapper }))
+}
+
+fn decoder_regex(input: Span) -> IResult<Decoder> {
+    let (rest, _) = char('/')(input)?;
+
+    // TODO: fix it! Less something less naive (e.g., escaped-strings-like parser).
+    if let Some(end_pos) = find_unescaped(*rest, '/') {
+        let (rest, regex) = take::<usize, Span, ParseError>(end_pos)(rest)?;
+        let (rest, _) = char('/')(rest)?;
+        return Ok((
+            rest,
+            Decoder::Regex {
+                regex: (*regex).replace(r#"\/"#, "/"),
+            },
+        ));
+    }
+
+    Err(nom::Err::Failure(ParseError::partial(
+        "regex",
+        "closing '/' symbol",
+        rest,
+    )))
+}
+
+fn redactor(input: Span) -> IResult<Redactor> {
+    let (rest, _) = tag_no_case("redact")(input)?;
+    let (rest, fields) = match maybe_lpadded(separated_list(
+        '{',
+        '}',
+        ',',
+        redacted_field,
+        "redact expression",
+        "transformed field (example: '.email | mask_email') or '}'",
+    ))(rest)
+    {
+        Ok((rest, fields)) => (rest, fields),
+        Err(nom::Err::Error(_)) => {
+            return Err(nom::Err::Failure(ParseError::partial(
+                "redact expression",
+                "comma-separated list of transformed fields",
+                rest,
+            )))
+        }
+        Err(e) => return Err(e),
+    };
+
+    if fields.is_empty() {
+        return Err(nom::Err::Failure(ParseError::new(
+            "redact expects at least one field".to_owned(),
+            rest,
+        )));
+    }
+
+    let mut seen = HashSet::new();
+    for field in fields.iter() {
+        if !seen.insert(&field.loc) {
+            return Err(nom::Err::Failure(ParseError::new(
+                "a field can be redacted only once".to_owned(),
+                rest,
+            )));
+        }
+    }
+
+    Ok((rest, Redactor { fields }))
+}
+
+// .client_ip | ip_truncate(16)
+// .msg | scrub("\d{16}") | lower
+fn redacted_field(input: Span) -> IResult<RedactedField> {
+    let (rest, mut expr) = field_pipeline(input)?;
+
+    let mut transforms = vec![];
+    while let FieldExpr::Transform(inner, transform) = expr {
+        transforms.push(transform);
+        expr = *inner;
+    }
+    transforms.reverse();
+
+    match expr {
+        FieldExpr::Field(loc) if !transforms.is_empty() => {
+            Ok((rest, RedactedField { loc, transforms }))
+        }
+        _ => Err(nom::Err::Failure(ParseError::new(
+            "redact expects a field followed by transformations (example: '.email | mask_email')"
+                .to_owned(),
+            input,
+        ))),
+    }
+}
+
+fn mapper(input: Span) -> IResult<Mapper> {
+    let (rest, _) = tag_no_case("map")(input)?;
+    let (rest, items) = match maybe_lpadded(separated_list(
+        '{',
+        '}',
+        ',',
+        mapper_item,
+        "map expression",
+        "field definition (example: '.foo:str') or '}'",
+    ))(rest)
+    {
+        Ok((rest, items)) => (rest, items),
+        Err(nom::Err::Error(_)) => {
+            return Err(nom::Err::Failure(ParseError::partial(
+                "map expression",
+                "comma-separated list of fields",
+                rest,
+            )))
+        }
+        Err(e) => return Err(e),
+    };
+
+    let mut fields = Vec::new();
+    let mut wildcard = None;
+    for item in items {
+        if wildcard.is_some() {
+            return Err(nom::Err::Failure(ParseError::new(
+                "wildcard must be the last item of map expression".to_owned(),
+                rest,
+            )));
+        }
+        match item {
+            MapperItem::Field(field) => fields.push(field),
+            MapperItem::Wildcard(w) => wildcard = Some(w),
+        }
+    }
+
+    let mut count_timestamps = 0;
+    let mut count_loc_by_name = 0;
+    let mut count_loc_by_pos = 0;
+    let mut end_names = HashSet::new();
+    for field in fields.iter() {
+        if let FieldType::Timestamp(..) = field.typ {
+            count_timestamps += 1;
+        }
+
+        if !end_names.insert(field.end_name()) {
+            return Err(nom::Err::Failure(ParseError::new(
+                format!(
+                    "ambiguous field name in map expression '{}'",
+                    field.end_name()
+                ),
+                rest,
+            )));
+        }
+
+        match field.typ {
+            FieldType::Const(_) | FieldType::Computed(_) => (), // noop for now
+            _ => match field.loc {
+                FieldLoc::Name(_) => count_loc_by_name += 1,
+                FieldLoc::Position(_) => count_loc_by_pos += 1,
+            },
+        }
+    }
+
+    if let Some(ref wildcard) = wildcard {
+        for loc in wildcard.except.iter() {
+            match loc {
+                FieldLoc::Name(_) => count_loc_by_name += 1,
+                FieldLoc::Position(_) => count_loc_by_pos += 1,
+            }
+        }
+    }
+
+    if fields.is_empty() && wildcard.is_none() {
+        return Err(nom::Err::Failure(ParseError::new(
+            "map expression must have at least one field definition (example: .1:str as some_name)"
+                .to_owned(),
+            rest,
+        )));
+    }
+    if count_timestamps > 1 {
+        return Err(nom::Err::Failure(ParseError::new(
+            "map expression cannot have more than one timestamp field definition".to_owned(),
+            rest,
+        )));
+    }
+    if count_loc_by_name > 0 && count_loc_by_pos > 0 {
+        return Err(nom::Err::Failure(ParseError::new(
+            "all field definition must be either position-based (.0, .1, etc) or name-based (.foo, .bar, etc)".to_owned(),
+            rest,
+        )));
+    }
+    if end_names.contains(NAME_FIELD) != end_names.contains(VALUE_FIELD) {
+        return Err(nom::Err::Failure(ParseError::new(
+            format!(
+                "map expression must define both {} and {} fields or none of them",
+                NAME_FIELD, VALUE_FIELD
+            ),
+            rest,
+        )));
+    }
+    if fields.iter().any(|f| {
+        f.end_name() == VALUE_FIELD
+            && matches!(
+                f.typ,
+                FieldType::Auto
+                    | FieldType::String
+                    | FieldType::Ip
+                    | FieldType::Const(_)
+                    | FieldType::Timestamp(..)
+            )
+    }) {
+        return Err(nom::Err::Failure(ParseError::new(
+            format!("{} field must be numeric", VALUE_FIELD),
+            rest,
+        )));
+    }
+
+    let mut mapper = Mapper {
+        fields,
+        wildcard,
+        timezone: None,
+        year: None,
+    };
+    mapper.share_template_miners();
+    Ok((rest, mapper))
+}
+
+fn mapper_item(input: Span) -> IResult<MapperItem> {
+    alt((
+        map(mapper_wildcard, MapperItem::Wildcard),
+        map(mapper_field, MapperItem::Field),
+    ))(input)
+}
+
+fn mapper_field(input: Span) -> IResult<MapperField> {
+    alt((
+        mapper_field_computed,
+        mapper_field_dynamic,
+        mapper_field_const,
+    ))(input)
+}
+
+// *
+// * except .foo, .bar
+fn mapper_wildcard(input: Span) -> IResult<Wildcard> {
+    let (rest, _) = char('*')(input)?;
+
+    let rest = match maybe_lpadded(tag_no_case("except "))(rest) {
+        Ok((rest, _)) => rest,
+        Err(nom::Err::Error(_)) => return Ok((rest, Wildcard { except: vec![] })),
+        Err(e) => return Err(e),
+    };
+
+    // Consumes the rest of the list, hence the wildcard must go last.
+    let (rest, except) = match separated_list1(
+        maybe_lpadded(char(',')),
+        maybe_lpadded(preceded(char('.'), mapper_field_loc)),
+    )(rest)
+    {
+        Ok((rest, except)) => (rest, except),
+        Err(nom::Err::Error(_)) => {
+            return Err(nom::Err::Failure(ParseError::partial(
+                "map expression",
+                "comma-separated list of fields to exclude (example: '.foo, .bar')",
+                rest,
+            )));
+        }
+        Err(e) => return Err(e),
+    };
+
+    Ok((rest, Wildcard { except }))
+}
+
+fn mapper_field_loc(input: Span) -> IResult<FieldLoc> {
+    alt((
+        map(digit1, |d: Span| {
+            FieldLoc::Position((*d).parse::<usize>().unwrap())
+        }),
+        map(label_identifier, FieldLoc::Name),
+    ))(input)
+}
+
+// .0:ts "%Y-%m-%d" as time
+// .1 as method
+// .foo:num
+// .qux:str as bar,
+// .error_code? :str
+// .retries:num = 0 as attempts
+fn mapper_field_dynamic(input: Span) -> IResult<MapperField> {
+    let (rest, _) = char('.')(input)?;
+
+    let (rest, loc) = match mapper_field_loc(rest) {
+        Ok((rest, loc)) => (rest, loc),
+        Err(nom::Err::Error(_)) => {
+            return Err(nom::Err::Failure(ParseError::partial(
+                "map expression",
+                "field position (number) or name (identifier)",
+                rest,
+            )));
+        }
+        Err(e) => return Err(e),
+    };
+
+    let (rest, optional) = match char::<Span, ParseError>('?')(rest) {
+        Ok((rest, _)) => (rest, true),
+        Err(_) => (rest, false),
+    };
+
+    let (rest, typ) = match maybe_lpadded(mapper_field_type)(rest) {
+        Ok((rest, typ)) => (rest, typ),
+        Err(nom::Err::Error(_)) => (rest, FieldType::Auto),
+        Err(e) => return Err(e),
+    };
+
+    let (rest, histogram) = match maybe_lpadded(mapper_field_histogram)(rest) {
+        Ok((rest2, histogram)) => match typ {
+            FieldType::Number | FieldType::Duration | FieldType::Bytes => (rest2, Some(histogram)),
+            _ => {
+                return Err(nom::Err::Failure(ParseError::new(
+                    "histogram requires a :num, :dur, or :bytes field".to_owned(),
+                    rest,
+                )))
+            }
+        },
+        Err(nom::Err::Error(_)) => (rest, None),
+        Err(e) => return Err(e),
+    };
+
+    let (rest, default) = match maybe_lpadded(mapper_field_default)(rest) {
+        Ok((rest, default)) => (rest, Some(default)),
+        Err(nom::Err::Error(_)) => (rest, None),
+        Err(e) => return Err(e),
+    };
+
+    let (rest, alias) = match maybe_lpadded(mapper_field_alias)(rest) {
+        Ok((rest, alias)) => (rest, Some(alias)),
+        Err(nom::Err::Error(_)) => (rest, None),
+        Err(e) => return Err(e),
+    };
+
+    Ok((
+        rest,
+        MapperField {
+            loc,
+            typ,
+            alias,
+            optional,
+            default,
+            histogram,
+        },
+    ))
+}
+
+// histogram(0.005, 0.01, 0.05)
+// histogram(exponential(0.001, 2, 10))
+// histogram(linear(0.1, 0.1, 5))
+fn mapper_field_histogram(input: Span) -> IResult<Histogram> {
+    let (rest, _) = tag_no_case("histogram")(input)?;
+
+    let (rest, args) = match maybe_lpadded(separated_list(
+        '(',
+        ')',
+        ',',
+        histogram_arg,
+        "histogram",
+        "comma-separated list of bucket bounds or a bucket generator",
+    ))(rest)
+    {
+        Ok((rest, args)) => (rest, args),
+        Err(nom::Err::Error(_)) => {
+            return Err(nom::Err::Failure(ParseError::partial(
+                "histogram",
+                "(",
+                rest,
+            )));
+        }
+        Err(e) => return Err(e),
+    };
+
+    let mut bounds = vec![];
+    let count = args.len();
+    for arg in args {
+        match arg {
+            HistogramArg::Bound(bound) => bounds.push(bound),
+            HistogramArg::Generated(generated) if count == 1 => bounds = generated,
+            HistogramArg::Generated(_) => {
+                return Err(nom::Err::Failure(ParseError::new(
+                    "histogram takes either bucket bounds or a single bucket generator".to_owned(),
+                    input,
+                )))
+            }
+        }
+    }
+
+    if bounds.is_empty()
+        || bounds.iter().any(|b| !b.is_finite())
+        || bounds.windows(2).any(|w| w[0] >= w[1])
+    {
+        return Err(nom::Err::Failure(ParseError::new(
+            "histogram bucket bounds must be finite and strictly increasing".to_owned(),
+            input,
+        )));
+    }
+
+    Ok((rest, Histogram { bounds }))
+}
+
+fn histogram_arg(input: Span) -> IResult<HistogramArg> {
+    alt((
+        map(histogram_generator, HistogramArg::Generated),
+        map(number_literal, HistogramArg::Bound),
+    ))(input)
+}
+
+// exponential(start, factor, count) or linear(start, width, count)
+fn histogram_generator(input: Span) -> IResult<Vec<f64>> {
+    let (rest, exponential) = alt((
+        value(true, tag_no_case("exponential")),
+        value(false, tag_no_case("linear")),
+    ))(input)?;
+
+    let (rest, args) = match maybe_lpadded(separated_list(
+        '(',
+        ')',
+        ',',
+        number_literal,
+        "bucket generator",
+        "comma-separated list of numbers",
+    ))(rest)
+    {
+        Ok((rest, args)) => (rest, args),
+        Err(nom::Err::Error(_)) => {
+            return Err(nom::Err::Failure(ParseError::partial(
+                "bucket generator",
+                "(",
+                rest,
+            )));
+        }
+        Err(e) => return Err(e),
+    };
+
+    let (start, step, count) = match args[..] {
+        [start, step, count] if count >= 1.0 && count.fract() == 0.0 => (start, step, count as i32),
+        _ => {
+            return Err(nom::Err::Failure(ParseError::new(
+                "bucket generator takes start, factor (or width), and a positive integer count"
+                    .to_owned(),
+                input,
+            )))
+        }
+    };
+
+    let bounds = (0..count).map(|i| {
+        round_bound(if exponential {
+            start * step.powi(i)
+        } else {
+            start + step * i as f64
+        })
+    });
+    Ok((rest, bounds.collect()))
+}
+
+// 0.1 + 0.1 * 2 -> 0.3 rather than 0.30000000000000004
+fn round_bound(bound: f64) -> f64 {
+    if bound == 0.0 || !bound.is_finite() {
+        return bound;
+    }
+    let scale = 10f64.powi(12 - bound.abs().log10().ceil() as i32);
+    (bound * scale).round() / scale
+}
+
+fn mapper_field_default(input: Span) -> IResult<String> {
+    let (rest, _) = char('=')(input)?;
+
+    match maybe_lpadded(alt((
+        string_literal,
+        map(recognize(number_literal), |n: Span| (*n).to_owned()),
+    )))(rest)
+    {
+        Ok((rest, default)) => Ok((rest, default)),
+        Err(nom::Err::Error(_)) => Err(nom::Err::Failure(ParseError::partial(
+            "map expression",
+            "default value (string or number literal)",
+            rest,
+        ))),
+        Err(e) => Err(e),
+    }
+}
+
+fn mapper_field_type(input: Span) -> IResult<FieldType> {
+    let (rest, _) = char(':')(input)?;
+
+    let (rest, typ) = match maybe_lpadded(alt((
+        value(FieldType::String, maybe_lpadded(tag_no_case("str"))),
+        value(FieldType::Ip, maybe_lpadded(tag_no_case("ip"))),
+        value(FieldType::Number, maybe_lpadded(tag_no_case("num"))),
+        value(FieldType::Duration, maybe_lpadded(tag_no_case("dur"))),
+        value(FieldType::Bytes, maybe_lpadded(tag_no_case("bytes"))),
+        value(
+            FieldType::Timestamp(None, None),
+            maybe_lpadded(tag_no_case("ts")),
+        ),
+    )))(rest)
+    {
+        Ok((rest, typ)) => (rest, typ),
+        Err(nom::Err::Error(_)) => {
+            return Err(nom::Err::Failure(ParseError::partial(
+                "map expression",
+                "type (str, num, dur, bytes, ip, or ts)",
+                rest,
+            )));
+        }
+        Err(e) => return Err(e),
+    };
+
+    if let FieldType::Timestamp(..) = typ {
+        let (rest, format) = match maybe_lpadded(string_literal)(rest) {
+            Ok((rest, format)) => (rest, Some(format)),
+            Err(nom::Err::Error(_)) => (rest, None),
+            Err(e) => return Err(e),
+        };
+        let (rest, tz) = match maybe_lpadded(mapper_field_timezone)(rest) {
+            Ok((rest, tz)) => (rest, Some(tz)),
+            Err(nom::Err::Error(_)) => (rest, None),
+            Err(e) => return Err(e),
+        };
+        return Ok((rest, FieldType::Timestamp(format, tz)));
+    }
+
+    Ok((rest, typ))
+}
+
+// tz "Europe/Berlin"
+fn mapper_field_timezone(input: Span) -> IResult<Timezone> {
+    let (rest, _) = tag_no_case("tz ")(input)?;
+
+    match maybe_lpadded(string_literal)(rest) {
+        Ok((rest2, tz)) => match parse_timezone(&tz) {
+            Ok(tz) => Ok((rest2, tz)),
+            Err(e) => Err(nom::Err::Failure(ParseError::new(e.to_string(), rest))),
+        },
+        Err(nom::Err::Error(_)) => Err(nom::Err::Failure(ParseError::partial(
+            "map expression",
+            "timezone (string literal)",
+            rest,
+        ))),
+        Err(e) => Err(e),
+    }
+}
+
+fn mapper_field_alias(input: Span) -> IResult<String> {
+    let (rest, _) = tag_no_case("as ")(input)?;
+
+    let (rest, alias) = match maybe_lpadded(label_identifier)(rest) {
+        Ok((rest, alias)) => (rest, alias),
+        Err(nom::Err::Error(_)) => {
+            return Err(nom::Err::Failure(ParseError::partial(
+                "map expression",
+                "alias (identifier)",
+                rest,
+            )));
+        }
+        Err(e) => return Err(e),
+    };
+
+    Ok((rest, alias))
+}
+
+// .bytes_sent / .request_time as throughput
+// concat(.host, ":", .port) as target
+// .path | regex_replace("/\d+", "/:id") as route
+// cidr_lookup(.addr, "subnets.csv") = "unknown" as dc
+fn mapper_field_computed(input: Span) -> IResult<MapperField> {
+    let (rest, expr) = field_pipeline(input)?;
+    if let FieldExpr::Field(_) = expr {
+        // Not an expression but a plain field, e.g. '.foo:num as bar'.
+        return Err(nom::Err::Error(ParseError::new(
+            "plain field".to_owned(),
+            input,
+        )));
+    }
+
+    let (rest, default) = match maybe_lpadded(mapper_field_default)(rest) {
+        Ok((rest, default)) => (rest, Some(default)),
+        Err(nom::Err::Error(_)) => (rest, None),
+        Err(e) => return Err(e),
+    };
+
+    let (rest, alias) = match maybe_lpadded(mapper_field_alias)(rest) {
+        Ok((rest, alias)) => (rest, alias),
+        Err(nom::Err::Error(_)) => match expr.transformed_field() {
+            // A transformed field keeps its name, e.g. '.method | lower'.
+            Some(FieldLoc::Name(name)) => (rest, name.clone()),
+            Some(FieldLoc::Position(pos)) => (rest, format!("f{}", pos)),
+            None => {
+                return Err(nom::Err::Failure(ParseError::partial(
+                    "map expression",
+                    "alias for computed field (example: '.foo / 1000 as bar')",
+                    rest,
+                )));
+            }
+        },
+        Err(e) => return Err(e),
+    };
+
+    Ok((
+        rest,
+        MapperField {
+            loc: FieldLoc::Name(alias),
+            typ: FieldType::Computed(expr),
+            alias: None,
+            optional: false,
+            default,
+            histogram: None,
+        },
+    ))
+}
+
+// extra_label: "value"
+fn mapper_field_const(input: Span) -> IResult<MapperField> {
+    let (rest, name) = label_identifier(input)?;
+
+    let rest = match maybe_lpadded(char(':'))(rest) {
+        Ok((rest, _)) => rest,
+        Err(nom::Err::Error(_)) => {
+            return Err(nom::Err::Failure(ParseError::partial(
+                "map expression",
+                ":",
+                rest,
+            )));
+        }
+        Err(e) => return Err(e),
+    };
+
+    let (rest, value) = match maybe_lpadded(string_literal)(rest) {
+        Ok((rest, value)) => (rest, value),
+        Err(nom::Err::Error(_)) => {
+            return Err(nom::Err::Failure(ParseError::partial(
+                "map expression",
+                "string literal",
+                rest,
+            )));
+        }
+        Err(e) => return Err(e),
+    };
+
+    Ok((
+        rest,
+        MapperField {
+            loc: FieldLoc::Name(name),
+            typ: FieldType::Const(value),
+            alias: None,
+            optional: false,
+            default: None,
+            histogram: None,
+        },
+    ))
+}
+
+// where .status >= 500 and .path !~ "^/health"
+fn filter(input: Span) -> IResult<FieldExpr> {
+    let (rest, _) = tag_no_case("where ")(input)?;
+    let (rest, expr) = match maybe_lpadded(field_expr(0))(rest) {
+        Ok((rest, expr)) => (rest, expr),
+        Err(nom::Err::Error(_)) => {
+            return Err(nom::Err::Failure(ParseError::partial(
+                "where",
+                "boolean expression (example: '.status >= 500')",
+                rest,
+            )));
+        }
+        Err(e) => return Err(e),
+    };
+    Ok((rest, expr))
+}
+
+// dedup
+// dedup by (.request_id, .status) within 5m
+fn dedup(input: Span) -> IResult<Dedup> {
+    let (rest, _) = tag_no_case("dedup")(input)?;
+
+    let (rest, fields) = match maybe_lpadded(tag_no_case("by"))(rest) {
+        Ok((rest, _)) => match maybe_lpadded(separated_list(
+            '(',
+            ')',
+            ',',
+            dedup_field,
+            "dedup",
+            "comma-separated list of fields (example: '.request_id')",
+        ))(rest)
+        {
+            Ok((rest, fields)) if !fields.is_empty() => (rest, fields),
+            Ok(_) | Err(nom::Err::Error(_)) => {
+                return Err(nom::Err::Failure(ParseError::partial(
+                    "dedup",
+                    "non-empty list of fields (example: 'by (.request_id)')",
+                    rest,
+                )))
+            }
+            Err(e) => return Err(e),
+        },
+        Err(nom::Err::Error(_)) => (rest, vec![]),
+        Err(e) => return Err(e),
+    };
+
+    let (rest, window) = match maybe_lpadded(tag_no_case("within"))(rest) {
+        Ok((rest, _)) => match maybe_lpadded(duration)(rest) {
+            Ok((rest, window)) => (rest, window),
+            Err(nom::Err::Error(_)) => {
+                return Err(nom::Err::Failure(ParseError::partial(
+                    "dedup",
+                    "window duration (example: '5m')",
+                    rest,
+                )))
+            }
+            Err(e) => return Err(e),
+        },
+        Err(nom::Err::Error(_)) => (rest, DEFAULT_DEDUP_WINDOW),
+        Err(e) => return Err(e),
+    };
+
+    Ok((rest, Dedup { fields, window }))
+}
+
+fn dedup_field(input: Span) -> IResult<String> {
+    preceded(char('.'), label_identifier)(input)
+}
+
+fn query(input: Span) -> IResult<QueryExpr> {
+    let (rest, _) = tag_no_case("select ")(input)?;
+    let (rest, expr) = match maybe_lpadded(query_expr(None))(rest) {
+        Ok((rest, ast)) => (rest, ast),
+        Err(nom::Err::Error(_)) => {
+            return Err(nom::Err::Failure(ParseError::partial(
+                "query",
+                "query expression",
+                rest,
+            )));
+        }
+        Err(e) => return Err(e),
+    };
+    Ok((rest, expr))
+}
+
+fn formatter(input: Span) -> IResult<Formatter> {
+    let (rest, fmt) = alt((
+        value(Formatter::JSON, tag_no_case("to_json")),
+        value(Formatter::PromAPI, tag_no_case("to_promapi")),
+    ))(input)?;
+    Ok((rest, fmt))
+}
+
+fn find_unescaped(stack: &str, needle: char) -> Option<usize> {
+    let mut armed = false;
+    for (i, c) in stack.chars().enumerate() {
+        if !armed && c == '\\' {
+            armed = true;
+            continue;
+        }
+
+        if !armed && c == needle {
+            return Some(i);
+        }
+
+        if armed {
+            armed = false;
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_valid_program() -> std::result::Result<(), String> {
+        #[rustfmt::skip]
+        let tests = [
+            r#"//"#,
+            r#"/foo/"#,
+            r#"/foo\/bar/"#,
+            r#"/.*(\\d+)foo\\s(\\w+).+/"#,
+            r#"json"#,
+            r#"json | to_json"#,
+            r#"json| to_json"#,
+            r#"json |to_json"#,
+            r#"json|to_json"#,
+            r#"json | to_promapi"#,
+            r#"/.*(\\d+)foo\\s(\\w+).+/ | to_json"#,
+            r#"/.*(\\d+)foo\\s(\\w+).+/ | map {foo: "bar"} | to_json"#,
+            r#"/.*(\\d+)foo\\s(\\w+).+/ | map {.0:str, .1:num as qux, .2:ts "%Y-%m-%d", foo: "bar"} | to_json"#,
+            r#"/.*(\\d+)foo\\s(\\w+).+/ | map {.foo:str as bar, .qux:num, .ts:ts "%Y-%m-%d", abc: "42"} | to_json"#,
+            r#"json | map {*}"#,
+            r#"json | map {.bytes_sent / .request_time as throughput, .upstream_ms / 1000 as upstream_s}"#,
+            r#"json | map {concat(.host, ":", .port) as target, (.status >= 500) * 1 as failed, *}"#,
+            r#"json | map {.path | regex_replace("/\d+", "/:id") as route, .ua | lower, .url | url_query("page") | trim as page}"#,
+            r#"json | map { .foo:num as bar, * }"#,
+            r#"json | map {.ts:ts, * except .foo, .bar}"#,
+            r#"/(\\d+) (\\w+)/ | map {.0:ts, * except .1}"#,
+            r#"json | map {.error_code? :str, .retries:num = 0 as attempts, .user? = "anonymous", .ts:ts = "2021-01-01T00:00:00Z"}"#,
+            r#"/(\\d+) (\\w+)/ | map {.0:ts, .1?, .2? :num = -1.5}"#,
+            r#"json | map {.latency:dur, .upstream_time:dur = "0s" as upstream, .size:bytes}"#,
+            r#"json | map {.ts:ts, .metric as __name__, .value:num as __value__, .queue}"#,
+            r#"json | map {.ts:ts, __name__: "queue_depth", .value:dur as __value__, *}"#,
+            r#"json | map {.ts:ts, .latency:num histogram(0.005, 0.01, 0.05, 0.1, 0.5, 1) as rt}"#,
+            r#"json | map {.ts:ts, .latency:dur histogram(exponential(0.001, 2, 10)), .size:bytes histogram( linear(0, 1000, 5), )}"#,
+            r#"json | map {.ts:ts "%Y-%m-%d %H:%M:%S" tz "Europe/Berlin" as time, .status:num}"#,
+            r#"/(\S+) (\d+)/ | map {.0:ts tz "-05:00", .1:num}"#,
+            r#"json | redact {.client_ip | ip_truncate, .email | mask_email, .msg | scrub("\d{16}")}"#,
+            r#"/(\S+) (\S+)/ | redact {.0 | ip_truncate(16, 32)} | map {.0 as subnet, .1:num}"#,
+            r#"json | map {.remote_addr:ip, cidr_bucket(.remote_addr, 24, 64) as subnet, cidr_bucket(.remote_addr, 16) as net16} | where in_cidr(.remote_addr, "10.0.0.0/8", "fd00::/8")"#,
+            r#"json | map {template(.msg) as pattern, template_id(.msg, 0.6) as pattern_id}"#,
+            r#"json | map {.ts:ts, .request_id} | dedup | select count_over_time(__events__[1m])"#,
+            r#"json | map {.ts:ts, .request_id, .status:num} | where .status > 0 | dedup by (.request_id, .status) within 5m"#,
+            r#"json | map {.status:num, .path} | where .status >= 500 and .path !~ "^/health""#,
+            r#"json | map {*} | where not (.user == "root" or .user =~ "^admin") | select count_over_time(status[1m]) | to_json"#,
+            r#"/.*(\\d+)foo\\s(\\w+).+/ | map {.foo:str as bar, .qux:num, .ts:ts "%Y-%m-%d", abc: "42"} | select {__name__=~"abc|foo"} / 9001 | to_json"#,
+        ];
+
+        for input in &tests {
+            parse_program(input).map_err(|e| format!("Got {:?} while parsing {}", e, input))?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_shared_template_miners() -> Result<()> {
+        let ast = parse_program(
+            r#"json | map {template(.msg) as a, template_id(.msg) as b, template(.msg, 0.6) as c, template(.other | lower) as d}"#,
+        )?;
+
+        let miners: Vec<_> = ast
+            .mapper
+            .unwrap()
+            .fields
+            .into_iter()
+            .map(|f| match f.typ {
+                FieldType::Computed(FieldExpr::Template(_, _, miner)) => miner,
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(miners[0], miners[1]);
+        assert_ne!(miners[0], miners[2]);
+        assert_ne!(miners[0], miners[3]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_histogram() -> std::result::Result<(), String> {
+        #[rustfmt::skip]
+        let tests = [
+            ("histogram(0.5, 1, 2.5)", Ok(vec![0.5, 1.0, 2.5])),
+            ("histogram(exponential(0.001, 10, 4))", Ok(vec![0.001, 0.01, 0.1, 1.0])),
+            ("histogram(linear(0.1, 0.1, 3))", Ok(vec![0.1, 0.2, 0.3])),
+            ("histogram(1, 0.5)", Err(())),
+            ("histogram()", Err(())),
+            ("histogram(1, linear(2, 1, 2))", Err(())),
+            ("histogram(linear(0, 1, 2.5))", Err(())),
+            ("histogram(exponential(1, 1, 3))", Err(())),
+        ];
+
+        for (input, expected) in &tests {
+            let program = format!("json | map {{ .latency:num {} }}", input);
+            let actual = parse_program(&program)
+                .map(|ast| {
+                    ast.mapper.unwrap().fields[0]
+                        .histogram
+                        .clone()
+                        .unwrap()
+                        .bounds
+                })
+                .map_err(|_| ());
+            assert_eq!(*expected, actual, "while parsing {}", input);
+        }
+
+        assert!(parse_program("json | map { .latency:str histogram(1, 2) }").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_metric_shaped_mapper() {
+        for input in &[
+            "json | map { .metric as __name__ }",
+            "json | map { .metric as __name__, .value as __value__ }",
+            "json | map { .metric as __name__, .value:str as __value__ }",
+            "json | map { .metric as __name__, .value:ts as __value__ }",
+        ] {
+            assert!(parse_program(input).is_err(), "while parsing {}", input);
+        }
+    }
+
+    #[test]
+    fn test_redactor() {
+        let ast =
+            parse_program("json | redact { .ip | ip_truncate(16) | trim, .email | mask_email }")
+                .unwrap();
+        let fields = ast.redactor.unwrap().fields;
+        assert_eq!(FieldLoc::Name("ip".to_owned()), fields[0].loc);
+        assert_eq!(
+            vec![Transform::IpTruncate(16, 48), Transform::Trim],
+            fields[0].transforms
+        );
+        assert_eq!(vec![Transform::MaskEmail], fields[1].transforms);
+
+        for input in &[
+            "json | redact {}",
+            "json | redact { .ip }",
+            "json | redact { concat(.a, .b) | lower }",
+            "json | redact { .ip | ip_truncate(33) }",
+            "json | redact { .ip | ip_truncate, .ip | lower }",
+            "json | redact { .msg | scrub(\"(\") }",
+            "json | redact { .user | hmac(\"PQ_TEST_UNSET_HMAC_KEY\") }",
+        ] {
+            assert!(parse_program(input).is_err(), "while parsing {}", input);
+        }
+    }
+
+    #[test]
+    fn test_switch_decoder() {
+        let ast = parse_program(
+            r#"switch { /GET (\S+)/ => map {.0 as path}, /ERROR (.+) (\d+)/ => map {.0 as error, .1:num}, } | select error"#,
+        )
+        .unwrap();
+        let arms = match ast.decoder {
+            Decoder::Switch(arms) => arms,
+            v => panic!("unexpected decoder {:?}", v),
+        };
+        assert_eq!(2, arms.len());
+        assert_eq!(r#"ERROR (.+) (\d+)"#, arms[1].regex);
+        assert_eq!("error", arms[1].mapper.fields[0].end_name());
+        assert!(ast.mapper.is_none());
+        assert!(ast.query.is_some());
+
+        for input in &[
+            "switch {}",
+            "switch { /(.+)/ }",
+            "switch { /(.+)/ => .0 }",
+            "switch { /(.+)/ => map {.0} } | map {.0}",
+            "switch { /(.+)/ => map {.0} } | redact {.0 | lower}",
+            "switch { json => map {.foo} }",
+        ] {
+            assert!(parse_program(input).is_err(), "while parsing {}", input);
+        }
+    }
+
+    #[test]
+    fn test_regex_decoder() -> std::result::Result<(), String> {
+        #[rustfmt::skip]
+        let tests = [
+            (r#"//"#, ""),
+            (r#"/foo/"#, "foo"),
+             (r#"/foo\/bar/"#, "foo/bar"),
+        ];
+
+        for (input, expected) in &tests {
+            let ast =
+                parse_program(input).map_err(|e| format!("Got {:?} while parsing {}", e, input))?;
+            match ast.decoder {
+                Decoder::Regex { regex: actual } => assert_eq!(*expected, actual),
+                v => panic!("unexpected decoder {:?} while parsing {}", v, input),
+            }
+        }
+        Ok(())
+    }
+}
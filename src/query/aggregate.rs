@@ -64,7 +64,7 @@ impl AggregateEvaluator {
             }
         }
 
-        InstantVector::new(v.timestamp(), agg.values().cloned().into_iter().collect())
+        InstantVector::new(v.timestamp(), agg.values().cloned().collect())
     }
 
     // shamefully copy-pasted from next_simple().
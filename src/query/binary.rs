@@ -359,7 +359,7 @@ fn scalar_op_scalar_ex(
     comp_value: SampleValue,
 ) -> Option<SampleValue> {
     match (op.kind(), bool_modifier, scalar_op_scalar(op, lv, rv)) {
-        (BinaryOpKind::Comparison, false, val) if val == 0.0 => None,
+        (BinaryOpKind::Comparison, false, 0.0) => None,
         (BinaryOpKind::Comparison, false, val) if (val - 1.0).abs() < f64::EPSILON => {
             Some(comp_value)
         }
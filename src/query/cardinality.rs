@@ -0,0 +1,332 @@
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+
+use crate::error::{Error, Result};
+use crate::model::{LabelName, LabelValue, Labels, LabelsTrait};
+
+/// Value the least frequent label values collapse into.
+pub const OTHER_LABEL_VALUE: &str = "__other__";
+
+const NAME_LABEL: &str = "__name__";
+
+/// `100` (any label) or `path=100` (a certain label).
+#[derive(Debug, Clone, PartialEq)]
+pub struct LabelLimit {
+    pub label: Option<LabelName>,
+    pub max_values: usize,
+}
+
+impl std::str::FromStr for LabelLimit {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (label, max_values) = match s.split_once('=') {
+            Some((label, max_values)) => (Some(label.trim().to_owned()), max_values),
+            None => (None, s),
+        };
+        match max_values.trim().parse::<usize>() {
+            Ok(max_values) if max_values > 0 => Ok(Self { label, max_values }),
+            _ => Err(Error::new(
+                "label limit must be a positive number, optionally prefixed with a label name (path=100)",
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OverflowPolicy {
+    /// The least frequent values become __other__.
+    Collapse,
+    /// The run fails naming the offending label.
+    Fail,
+}
+
+impl std::str::FromStr for OverflowPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "collapse" => Ok(OverflowPolicy::Collapse),
+            "fail" => Ok(OverflowPolicy::Fail),
+            _ => Err(Error::new(
+                "cardinality overflow policy must be collapse or fail",
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CardinalityLimits {
+    pub labels: Vec<LabelLimit>,
+    pub max_series: Option<usize>,
+    pub policy: OverflowPolicy,
+}
+
+impl Default for CardinalityLimits {
+    fn default() -> Self {
+        Self {
+            labels: vec![],
+            max_series: None,
+            policy: OverflowPolicy::Collapse,
+        }
+    }
+}
+
+impl CardinalityLimits {
+    fn label_limit(&self, label: &str) -> Option<usize> {
+        self.labels
+            .iter()
+            .find(|l| l.label.as_deref() == Some(label))
+            .or_else(|| self.labels.iter().find(|l| l.label.is_none()))
+            .map(|l| l.max_values)
+    }
+}
+
+/// Keeps the number of distinct label values and series within the limits.
+pub struct CardinalityGuard {
+    limits: CardinalityLimits,
+    values: HashMap<LabelName, TopK<LabelValue>>,
+    series: Option<TopK<Vec<u8>>>,
+    // Only to name the culprit label when the series limit is exceeded.
+    series_values: HashMap<LabelName, HashSet<LabelValue>>,
+}
+
+impl CardinalityGuard {
+    pub fn new(limits: CardinalityLimits) -> Self {
+        Self {
+            series: limits.max_series.map(TopK::new),
+            values: HashMap::new(),
+            series_values: HashMap::new(),
+            limits,
+        }
+    }
+
+    /// Applies the per-label limits to the labels of a record.
+    pub fn check_labels(&mut self, labels: &mut Labels) -> Result<()> {
+        if self.limits.labels.is_empty() {
+            return Ok(());
+        }
+
+        for (name, value) in labels.iter_mut() {
+            let max_values = match self.limits.label_limit(name) {
+                Some(max_values) if name != NAME_LABEL => max_values,
+                _ => continue,
+            };
+            let top = self
+                .values
+                .entry(name.clone())
+                .or_insert_with(|| TopK::new(max_values));
+            if top.admit(value) {
+                continue;
+            }
+            match self.limits.policy {
+                OverflowPolicy::Collapse => *value = OTHER_LABEL_VALUE.to_owned(),
+                OverflowPolicy::Fail => {
+                    return Err(Error::from(format!(
+                        "label '{}' has more than {} distinct values",
+                        name, max_values
+                    )))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies the global series limit to the labels (including the name) of a sample.
+    pub fn check_series(&mut self, labels: &mut Labels) -> Result<()> {
+        let series = match self.series {
+            Some(ref mut series) => series,
+            None => return Ok(()),
+        };
+
+        if series.admit(&labels.to_vec()) {
+            if self.limits.policy == OverflowPolicy::Fail {
+                for (name, value) in labels.iter() {
+                    self.series_values
+                        .entry(name.clone())
+                        .or_default()
+                        .insert(value.clone());
+                }
+            }
+            return Ok(());
+        }
+
+        match self.limits.policy {
+            OverflowPolicy::Collapse => {
+                for (name, value) in labels.iter_mut() {
+                    if name != NAME_LABEL {
+                        *value = OTHER_LABEL_VALUE.to_owned();
+                    }
+                }
+                Ok(())
+            }
+            OverflowPolicy::Fail => {
+                let culprit = self
+                    .series_values
+                    .iter()
+                    .filter(|(name, _)| *name != NAME_LABEL)
+                    .max_by_key(|(name, values)| (values.len(), std::cmp::Reverse(*name)))
+                    .map(|(name, values)| {
+                        format!(", label '{}' has {} distinct values", name, values.len())
+                    })
+                    .unwrap_or_default();
+                Err(Error::from(format!(
+                    "more than {} series{}",
+                    series.capacity, culprit
+                )))
+            }
+        }
+    }
+}
+
+/// Keeps (approximately) the `capacity` most frequent items. Once the capacity
+/// is reached, new items are rejected until they turn out to be more frequent
+/// than the least frequent kept one, which is then evicted. Rejected (and evicted)
+/// items are counted in a Space-Saving sketch of the same capacity, so memory is
+/// bounded. Note that an evicted label value makes its series continue as the
+/// __other__ one, i.e. the series gets split in two.
+struct TopK<T> {
+    capacity: usize,
+    kept: HashMap<T, u64>,
+    candidates: HashMap<T, u64>,
+}
+
+impl<T: Clone + Eq + Hash + Ord> TopK<T> {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            kept: HashMap::new(),
+            candidates: HashMap::new(),
+        }
+    }
+
+    // TODO: optimize - finding the least frequent items is O(capacity).
+    fn admit(&mut self, item: &T) -> bool {
+        if let Some(count) = self.kept.get_mut(item) {
+            *count += 1;
+            return true;
+        }
+        if self.kept.len() < self.capacity {
+            self.kept.insert(item.clone(), 1);
+            return true;
+        }
+
+        let count = match self.candidates.get_mut(item) {
+            Some(count) => {
+                *count += 1;
+                *count
+            }
+            None => {
+                let mut count = 1;
+                if self.candidates.len() >= self.capacity {
+                    let (least, least_count) = least_frequent(&self.candidates);
+                    self.candidates.remove(&least);
+                    count += least_count;
+                }
+                self.candidates.insert(item.clone(), count);
+                count
+            }
+        };
+
+        let (least, least_count) = least_frequent(&self.kept);
+        if count <= least_count {
+            return false;
+        }
+        self.kept.remove(&least);
+        self.candidates.remove(item);
+        self.kept.insert(item.clone(), count);
+        // The evicted item keeps its count to be able to win its place back.
+        self.candidates.insert(least, least_count);
+        true
+    }
+}
+
+// Ties are broken by the item itself to keep the eviction order deterministic.
+fn least_frequent<T: Clone + Ord>(counts: &HashMap<T, u64>) -> (T, u64) {
+    counts
+        .iter()
+        .min_by(|(a, a_count), (b, b_count)| a_count.cmp(b_count).then_with(|| b.cmp(a)))
+        .map(|(item, count)| (item.clone(), *count))
+        .expect("non-empty counts")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(pairs: &[(&str, &str)]) -> Labels {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_collapse_label_values() -> Result<()> {
+        let mut guard = CardinalityGuard::new(CardinalityLimits {
+            labels: vec!["2".parse()?, "method=3".parse()?],
+            ..Default::default()
+        });
+
+        #[rustfmt::skip]
+        let tests = [
+            (("/a", "GET"), ("/a", "GET")),
+            (("/b", "POST"), ("/b", "POST")),
+            (("/a", "PUT"), ("/a", "PUT")),
+            // Limits reached.
+            (("/c", "PATCH"), ("__other__", "__other__")),
+            (("/a", "GET"), ("/a", "GET")),
+            // /c is now seen twice, /b only once.
+            (("/c", "GET"), ("/c", "GET")),
+            (("/b", "GET"), ("__other__", "GET")),
+            // Evicted /b kept its count, so it's back as soon as it's more frequent than /c.
+            (("/b", "GET"), ("/b", "GET")),
+        ];
+
+        for ((path, method), (expected_path, expected_method)) in tests.iter() {
+            let mut actual = labels(&[("path", path), ("method", method)]);
+            guard.check_labels(&mut actual)?;
+            assert_eq!(
+                labels(&[("path", expected_path), ("method", expected_method)]),
+                actual
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_series_limit() -> Result<()> {
+        let limits = CardinalityLimits {
+            max_series: Some(2),
+            ..Default::default()
+        };
+
+        let mut guard = CardinalityGuard::new(limits.clone());
+        for id in &["1", "2", "1"] {
+            let mut actual = labels(&[("__name__", "bytes"), ("id", id)]);
+            guard.check_series(&mut actual)?;
+            assert_eq!(labels(&[("__name__", "bytes"), ("id", id)]), actual);
+        }
+        let mut actual = labels(&[("__name__", "bytes"), ("id", "3")]);
+        guard.check_series(&mut actual)?;
+        assert_eq!(
+            labels(&[("__name__", "bytes"), ("id", "__other__")]),
+            actual
+        );
+
+        let mut guard = CardinalityGuard::new(CardinalityLimits {
+            policy: OverflowPolicy::Fail,
+            ..limits
+        });
+        guard.check_series(&mut labels(&[("id", "1"), ("method", "GET")]))?;
+        guard.check_series(&mut labels(&[("id", "2"), ("method", "GET")]))?;
+        let err = guard
+            .check_series(&mut labels(&[("id", "3"), ("method", "GET")]))
+            .unwrap_err();
+        assert_eq!(
+            "more than 2 series, label 'id' has 2 distinct values",
+            err.to_string()
+        );
+        Ok(())
+    }
+}
@@ -7,7 +7,7 @@ use super::binary::create_binary_evaluator;
 use super::function::{create_func_evaluator, FuncCallArg};
 use super::identity::IdentityEvaluator;
 use super::parser::ast::*;
-use super::sample::SampleReader;
+use super::sample::{SampleOptions, SampleReader};
 use super::unary::UnaryEvaluator;
 use super::value::{QueryValue, QueryValueIter, QueryValueKind};
 use super::vector::VectorSelectorEvaluator;
@@ -19,6 +19,7 @@ const DEFAULT_INTERVAL: Duration = Duration::from_millis(1000);
 
 pub struct QueryEvaluator {
     inner: Box<dyn QueryValueIter>,
+    samples: Rc<RefCell<SampleReader>>,
     drained: bool,
 }
 
@@ -28,6 +29,7 @@ impl QueryEvaluator {
         records: Box<dyn std::iter::Iterator<Item = Result<Record>>>,
         interval: Option<Duration>,
         lookback: Option<Duration>,
+        samples: SampleOptions,
         start_at: Option<Timestamp>,
         verbose: bool, // TODO: remove it
     ) -> Result<Self> {
@@ -36,17 +38,18 @@ impl QueryEvaluator {
             .unwrap_or(DEFAULT_INTERVAL);
         assert!(interval.as_secs() + (interval.subsec_nanos() as u64) > 0);
 
+        let ctx = Context::new(
+            records,
+            interval,
+            lookback.unwrap_or(interval),
+            samples,
+            start_at,
+            verbose,
+        );
+
         Ok(Self {
-            inner: create_value_iter(
-                &Context::new(
-                    records,
-                    interval,
-                    lookback.unwrap_or(interval),
-                    start_at,
-                    verbose,
-                ),
-                query,
-            ),
+            samples: Rc::clone(&ctx.samples),
+            inner: create_value_iter(&ctx, query),
             drained: false,
         })
     }
@@ -65,7 +68,16 @@ impl std::iter::Iterator for QueryEvaluator {
             self.drained = true;
         }
 
-        self.inner.next().map(Ok)
+        let value = self.inner.next();
+
+        // The error that made the sample reading stop (e.g. an exceeded
+        // cardinality limit) ends the evaluation, and the value computed
+        // from the incomplete samples is discarded.
+        if let Some(e) = self.samples.borrow_mut().take_failure() {
+            self.drained = true;
+            return Some(Err(e));
+        }
+        value.map(Ok)
     }
 }
 
@@ -81,11 +93,12 @@ impl Context {
         records: Box<dyn std::iter::Iterator<Item = Result<Record>>>,
         interval: Duration,
         lookback: Duration,
+        samples: SampleOptions,
         start_at: Option<Timestamp>,
         verbose: bool,
     ) -> Self {
         Self {
-            samples: Rc::new(RefCell::new(SampleReader::new(records, verbose))),
+            samples: Rc::new(RefCell::new(SampleReader::new(records, samples, verbose))),
             interval,
             lookback,
             start_at,
@@ -130,6 +143,7 @@ fn create_value_iter(ctx: &Context, node: Expr) -> Box<dyn QueryValueIter> {
                 .map(|arg| match arg {
                     FunctionCallArg::Number(n) => FuncCallArg::Number(n),
                     FunctionCallArg::String(s) => FuncCallArg::String(s),
+                    FunctionCallArg::Routes(routes) => FuncCallArg::Routes(routes),
                     FunctionCallArg::Expr(expr) => {
                         FuncCallArg::ValueIter(create_value_iter(ctx, *expr))
                     }
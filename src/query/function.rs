@@ -1,13 +1,16 @@
 use std::collections::HashSet;
+use std::sync::Arc;
 
 use super::parser::ast::FunctionName;
 use super::value::{InstantVector, QueryValue, QueryValueIter, QueryValueKind, RangeVector};
-use crate::model::{LabelValue, LabelsTrait, SampleValue};
+use crate::model::{LabelName, LabelValue, LabelsTrait, SampleValue};
+use crate::utils::route::RouteTable;
 
 pub(super) enum FuncCallArg {
     Number(f64),
     String(LabelValue),
     ValueIter(Box<dyn QueryValueIter>),
+    Routes(Arc<RouteTable>),
 }
 
 pub(super) fn create_func_evaluator(
@@ -24,6 +27,47 @@ pub(super) fn create_func_evaluator(
             }
             panic!("unexpected argument type");
         }
+        Clamp | ClampMax | ClampMin => {
+            let mut args = args.into_iter();
+            let inner = match args.next() {
+                Some(FuncCallArg::ValueIter(inner)) => inner,
+                _ => panic!("unexpected argument type"),
+            };
+            let mut bounds = args.map(|arg| match arg {
+                FuncCallArg::Number(n) => n,
+                _ => panic!("unexpected argument type"),
+            });
+            let (min, max) = match func_name {
+                Clamp => (bounds.next(), bounds.next()),
+                ClampMax => (None, bounds.next()),
+                _ => (bounds.next(), None),
+            };
+            Box::new(ClampFuncEvaluator {
+                inner,
+                min: min.unwrap_or(SampleValue::NEG_INFINITY),
+                max: max.unwrap_or(SampleValue::INFINITY),
+            })
+        }
+        LabelRoute => {
+            let mut args = args.into_iter();
+            let inner = match args.next() {
+                Some(FuncCallArg::ValueIter(inner)) => inner,
+                _ => panic!("unexpected argument type"),
+            };
+            match (args.next(), args.next(), args.next()) {
+                (
+                    Some(FuncCallArg::String(dst_label)),
+                    Some(FuncCallArg::String(src_label)),
+                    Some(FuncCallArg::Routes(routes)),
+                ) => Box::new(LabelRouteFuncEvaluator {
+                    inner,
+                    dst_label,
+                    src_label,
+                    routes,
+                }),
+                _ => panic!("unexpected argument type"),
+            }
+        }
         _ => unimplemented!("Coming soon..."),
     }
 }
@@ -97,3 +141,91 @@ impl QueryValueIter for AggOverTimeFuncEvaluator {
         QueryValueKind::InstantVector
     }
 }
+
+/// clamp(v, min, max), clamp_min(v, min), clamp_max(v, max) - limits the
+/// sample values. Nothing is returned if min is greater than max.
+struct ClampFuncEvaluator {
+    inner: Box<dyn QueryValueIter>,
+    min: SampleValue,
+    max: SampleValue,
+}
+
+impl std::iter::Iterator for ClampFuncEvaluator {
+    type Item = QueryValue;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let v = match self.inner.next() {
+            Some(QueryValue::InstantVector(v)) => v,
+            None => return None,
+            _ => unreachable!("bug"),
+        };
+
+        let samples = if self.min > self.max {
+            vec![]
+        } else {
+            v.samples()
+                .iter()
+                .map(|(labels, value)| {
+                    (
+                        labels.without(&HashSet::new()), // trick to remove __name__
+                        value.max(self.min).min(self.max),
+                    )
+                })
+                .collect()
+        };
+        Some(QueryValue::InstantVector(InstantVector::new(
+            v.timestamp(),
+            samples,
+        )))
+    }
+}
+
+impl QueryValueIter for ClampFuncEvaluator {
+    fn value_kind(&self) -> QueryValueKind {
+        QueryValueKind::InstantVector
+    }
+}
+
+/// label_route(v, "route", "path"[, "/users/:id", ...]) - sets the `route`
+/// label to the route of the `path` label (see RouteTable).
+struct LabelRouteFuncEvaluator {
+    inner: Box<dyn QueryValueIter>,
+    dst_label: LabelName,
+    src_label: LabelName,
+    routes: Arc<RouteTable>,
+}
+
+impl std::iter::Iterator for LabelRouteFuncEvaluator {
+    type Item = QueryValue;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let v = match self.inner.next() {
+            Some(QueryValue::InstantVector(v)) => v,
+            None => return None,
+            _ => unreachable!("bug"),
+        };
+
+        let samples = v
+            .samples()
+            .iter()
+            .map(|(labels, value)| {
+                let mut labels = labels.clone();
+                let route = self
+                    .routes
+                    .normalize(labels.get(&self.src_label).map_or("", |s| s.as_str()));
+                labels.insert(self.dst_label.clone(), route);
+                (labels, *value)
+            })
+            .collect();
+        Some(QueryValue::InstantVector(InstantVector::new(
+            v.timestamp(),
+            samples,
+        )))
+    }
+}
+
+impl QueryValueIter for LabelRouteFuncEvaluator {
+    fn value_kind(&self) -> QueryValueKind {
+        QueryValueKind::InstantVector
+    }
+}
@@ -1,5 +1,6 @@
 mod aggregate;
 mod binary;
+mod cardinality;
 mod evaluator;
 mod function;
 mod identity;
@@ -10,5 +11,7 @@ mod vector;
 
 pub mod parser;
 
+pub use cardinality::{CardinalityLimits, LabelLimit, OverflowPolicy};
 pub use evaluator::QueryEvaluator;
+pub use sample::SampleOptions;
 pub use value::{InstantVector, QueryValue, RangeVector};
@@ -1,8 +1,10 @@
 use std::collections::HashSet;
+use std::sync::Arc;
 use std::time::Duration;
 
 use crate::error::{Error, Result};
 use crate::model::{LabelMatcher, LabelName, MetricName, SampleValue};
+use crate::utils::route::RouteTable;
 
 #[derive(Debug, PartialEq)]
 pub enum Expr {
@@ -296,6 +298,7 @@ pub enum FunctionName {
     ClampMax,
     ClampMin,
     LabelReplace,
+    LabelRoute,
     Vector,
 
     // agg over time
@@ -318,6 +321,7 @@ impl std::convert::TryFrom<&str> for FunctionName {
             "clamp_max" => Ok(ClampMax),
             "clamp_min" => Ok(ClampMin),
             "label_replace" => Ok(LabelReplace),
+            "label_route" => Ok(LabelRoute),
             "vector" => Ok(Vector),
             // agg over time
             "avg_over_time" => Ok(AvgOverTime),
@@ -336,6 +340,7 @@ pub enum FunctionCallArg {
     Expr(Box<Expr>),
     Number(f64),
     String(LabelName),
+    Routes(Arc<RouteTable>),
 }
 
 #[derive(Debug, PartialEq)]
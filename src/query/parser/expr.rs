@@ -1,4 +1,5 @@
 use std::convert::TryFrom;
+use std::sync::Arc;
 
 use lazy_static::lazy_static;
 use nom::{
@@ -15,6 +16,7 @@ use crate::utils::parse::{
     label_identifier, maybe_lpadded, number_literal, separated_list, string_literal, IResult,
     ParseError, Span,
 };
+use crate::utils::route::RouteTable;
 
 pub fn expr<'a>(min_prec: Option<Precedence>) -> impl FnMut(Span<'a>) -> IResult<Expr> {
     lazy_static! {
@@ -422,10 +424,11 @@ fn expr_function_call(input: Span) -> IResult<Expr> {
 
 fn function_name(input: Span) -> IResult<FunctionName> {
     let (rest, name) = alt((
-        tag_no_case("clamp"),
         tag_no_case("clamp_max"),
         tag_no_case("clamp_min"),
+        tag_no_case("clamp"),
         tag_no_case("count_over_time"),
+        tag_no_case("label_route"),
         tag_no_case("last_over_time"),
         tag_no_case("max_over_time"),
         tag_no_case("min_over_time"),
@@ -452,10 +455,27 @@ fn function_call(func_name: FunctionName, input: Span) -> IResult<FunctionCall>
             call_arg_string,
             call_arg_string,
         ],
+        LabelRoute => vec![call_arg_instant_vector, call_arg_string, call_arg_string],
         Vector => vec![call_arg_number],
     };
 
-    let (rest, args) = function_call_args(&arg_parsers, input)?;
+    let (mut rest, mut args) = function_call_args(&arg_parsers, input)?;
+
+    if func_name == LabelRoute {
+        // Optional route templates.
+        let mut templates = Vec::new();
+        while let Ok((tmp_rest, _)) = maybe_lpadded(char(','))(rest) {
+            let (tmp_rest, template) = maybe_lpadded(call_arg_string)(tmp_rest)?;
+            if let FunctionCallArg::String(template) = template {
+                templates.push(template);
+            }
+            rest = tmp_rest;
+        }
+        match RouteTable::new(&templates) {
+            Ok(routes) => args.push(FunctionCallArg::Routes(Arc::new(routes))),
+            Err(e) => return Err(nom::Err::Failure(ParseError::new(e.to_string(), input))),
+        }
+    }
 
     Ok((rest, FunctionCall::new(func_name, args)))
 }
@@ -581,6 +601,9 @@ mod tests {
             "(foo)",
             "(1 + 2) * 3",
             "vector(3) + count_over_time(clamp(foo{qux!~\"42\"}, -100, 100500))",
+            "clamp_max(foo, 5) + clamp_min(foo, 1)",
+            "sum by (route) (label_route(foo, \"route\", \"path\"))",
+            "label_route(foo, \"route\", \"path\", \"/users/:id\", \"/static/*\")",
         ];
 
         for input in &tests {
@@ -719,8 +742,8 @@ mod tests {
             match expr {
                 Expr::BinaryOperation(e) => extract_operators(e.lhs())
                     .into_iter()
-                    .chain(extract_operators(e.rhs()).into_iter())
-                    .chain(vec![e.op()].into_iter())
+                    .chain(extract_operators(e.rhs()))
+                    .chain(vec![e.op()])
                     .collect(),
                 Expr::UnaryOperation(_, e) => extract_operators(e.as_ref()),
                 _ => vec![],
@@ -58,7 +58,8 @@ fn label_matchers(input: Span) -> IResult<Vec<LabelMatcher>> {
 }
 
 fn label_matcher(input: Span) -> IResult<LabelMatcher> {
-    // IDENTIFIER match_op STRING
+    //   IDENTIFIER match_op STRING
+    // | IDENTIFIER ("=" | "!=") ip(STRING)
 
     let (rest, label) = label_identifier(input)?;
 
@@ -73,15 +74,20 @@ fn label_matcher(input: Span) -> IResult<LabelMatcher> {
         }
     };
 
-    let (rest, value) = match maybe_lpadded(string_literal)(rest) {
-        Ok(v) => v,
-        Err(_) => {
-            return Err(nom::Err::Failure(ParseError::partial(
-                "label matching",
-                "label value as string literal",
-                rest,
-            )))
-        }
+    let (rest, op, value) = match (op, maybe_lpadded(ip_literal)(rest)) {
+        (MatchOp::Eql, Ok((rest, cidr))) => (rest, MatchOp::EqlIp, cidr),
+        (MatchOp::Neq, Ok((rest, cidr))) => (rest, MatchOp::NeqIp, cidr),
+        (_, Err(nom::Err::Failure(e))) => return Err(nom::Err::Failure(e)),
+        _ => match maybe_lpadded(string_literal)(rest) {
+            Ok((rest, value)) => (rest, op, value),
+            Err(_) => {
+                return Err(nom::Err::Failure(ParseError::partial(
+                    "label matching",
+                    "label value as string literal",
+                    rest,
+                )))
+            }
+        },
     };
 
     let matcher = LabelMatcher::new(label, op, value)
@@ -90,6 +96,27 @@ fn label_matcher(input: Span) -> IResult<LabelMatcher> {
     Ok((rest, matcher))
 }
 
+// ip("10.0.0.0/8")
+fn ip_literal(input: Span) -> IResult<String> {
+    let (rest, _) = tag("ip")(input)?;
+    let (rest, _) = maybe_lpadded(char('('))(rest)?;
+    match maybe_lpadded(string_literal)(rest) {
+        Ok((rest, cidr)) => match maybe_lpadded(char(')'))(rest) {
+            Ok((rest, _)) => Ok((rest, cidr)),
+            Err(_) => Err(nom::Err::Failure(ParseError::partial(
+                "label matching",
+                ")",
+                rest,
+            ))),
+        },
+        Err(_) => Err(nom::Err::Failure(ParseError::partial(
+            "label matching",
+            "CIDR as string literal",
+            rest,
+        ))),
+    }
+}
+
 fn match_op(input: Span) -> IResult<MatchOp> {
     let (rest, m) = alt((tag("=~"), tag("!~"), tag("!="), tag("=")))(input)?;
     Ok((
@@ -290,6 +317,38 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_label_matcher_ip() -> std::result::Result<(), ParseError<'static>> {
+        #[rustfmt::skip]
+        let tests = [
+            (r#"addr=ip("10.0.0.0/8")"#, MatchOp::EqlIp, vec![("10.1.2.3", true), ("10.1.2.3:80", true), ("11.1.2.3", false), ("foo", false)]),
+            (r#"addr != ip ( "2001:db8::/32" )"#, MatchOp::NeqIp, vec![("2001:db8::1", false), ("::1", true), ("foo", true)]),
+        ];
+
+        for (input, expected_op, values) in tests.iter() {
+            let (rest, matcher) = label_matcher(Span::new(input))?;
+            assert_eq!(0, rest.len(), "unparsed input while parsing {}", input);
+            assert_eq!(expected_op, matcher.match_op());
+            for (value, expected) in values.iter() {
+                assert_eq!(*expected, matcher.matches(value), "{} {}", input, value);
+            }
+        }
+
+        for input in &[
+            r#"addr=ip("10.0.0.0/33")"#,
+            r#"addr=ip(10)"#,
+            r#"addr=ip("::1""#,
+            r#"addr=~ip("::1")"#,
+        ] {
+            assert!(
+                label_matcher(Span::new(input)).is_err(),
+                "while parsing {}",
+                input
+            );
+        }
+        Ok(())
+    }
+
     #[test]
     fn test_label_matcher_partial() {
         #[rustfmt::skip]
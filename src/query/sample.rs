@@ -1,10 +1,14 @@
 use std::cell::RefCell;
-use std::collections::VecDeque;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, VecDeque};
 use std::rc::{Rc, Weak};
+use std::time::Duration;
 
-use crate::error::Result;
-use crate::model::{Labels, MetricName, SampleValue, Timestamp};
+use super::cardinality::{CardinalityGuard, CardinalityLimits};
+use crate::error::{Error, Result};
+use crate::model::{Labels, MetricName, SampleValue, Timestamp, TimestampTrait};
 use crate::parse::Record;
+use crate::program::{NAME_FIELD, VALUE_FIELD};
 
 #[derive(Debug)]
 pub struct Sample {
@@ -48,8 +52,38 @@ impl Sample {
     }
 }
 
+/// Series with a sample of value 1 for every record, e.g. count_over_time(__events__[1m]).
+pub const DEFAULT_EVENT_SERIES: &str = "__events__";
+
+/// How records are turned into samples.
+#[derive(Debug, Clone)]
+pub struct SampleOptions {
+    /// Records out of timestamp order by up to this duration are reordered
+    /// (and later ones are dropped). No reordering if None.
+    pub max_lateness: Option<Duration>,
+    pub event_series: MetricName,
+    pub cardinality: CardinalityLimits,
+}
+
+impl Default for SampleOptions {
+    fn default() -> Self {
+        Self {
+            max_lateness: None,
+            event_series: DEFAULT_EVENT_SERIES.to_owned(),
+            cardinality: CardinalityLimits::default(),
+        }
+    }
+}
+
 pub struct SampleReader {
     records: Box<dyn std::iter::Iterator<Item = Result<Record>>>,
+    reorder: Option<ReorderBuffer>,
+    event_series: MetricName,
+    // Whether a record series clashing with the event series has been reported.
+    event_clash_reported: bool,
+    guard: CardinalityGuard,
+    // Stops reading, e.g. when a cardinality limit is exceeded.
+    failure: Option<Error>,
     cursors: Vec<Weak<Cursor>>,
     verbose: bool, // TODO: remove it
 }
@@ -57,10 +91,16 @@ pub struct SampleReader {
 impl SampleReader {
     pub fn new(
         records: Box<dyn std::iter::Iterator<Item = Result<Record>>>,
+        options: SampleOptions,
         verbose: bool,
     ) -> Self {
         Self {
             records,
+            reorder: options.max_lateness.map(ReorderBuffer::new),
+            event_series: options.event_series,
+            event_clash_reported: false,
+            guard: CardinalityGuard::new(options.cardinality),
+            failure: None,
             cursors: vec![],
             verbose,
         }
@@ -72,47 +112,208 @@ impl SampleReader {
         cursor
     }
 
+    pub fn take_failure(&mut self) -> Option<Error> {
+        self.failure.take()
+    }
+
     fn refill_cursors(&mut self) {
+        if self.failure.is_some() {
+            return;
+        }
+
         // TODO: optimize - read multiple records at once.
-        // TODO: propagate errors.
-        loop {
-            match self.records.next() {
-                Some(Ok(record)) => {
-                    let (line_no, timestamp, labels, mut values) = (
-                        record.line_no(),
-                        record.timestamp(),
-                        record.labels(),
-                        record.values().clone(),
-                    );
-
-                    if let Some(timestamp) = timestamp {
-                        // Tiny hack...
-                        values.insert("__line__".to_owned(), line_no as SampleValue);
-
-                        for (name, value) in values {
-                            let sample =
-                                Rc::new(Sample::new(name, value, timestamp, labels.clone()));
-
-                            for weak_cursor in self.cursors.iter_mut() {
-                                if let Some(cursor) = weak_cursor.upgrade() {
-                                    cursor.buffer.borrow_mut().push_front(sample.clone());
-                                }
-                            }
-                        }
-
-                        break;
+        if let Some(record) = self.next_record() {
+            let (timestamp, labels, mut values) = (
+                record
+                    .timestamp()
+                    .expect("records without timestamps are skipped"),
+                record.labels(),
+                record.values().clone(),
+            );
+
+            // Metric-shaped records: __value__ is a sample of the metric named by __name__.
+            // Without a numeric __value__, the mapped __name__ is dropped, since every
+            // sample gets the __name__ of its own series anyway.
+            let mut labels = labels.clone();
+            let name = labels.remove(NAME_FIELD);
+            if let Some(value) = values.remove(VALUE_FIELD) {
+                values.insert(name.unwrap_or_else(|| VALUE_FIELD.to_owned()), value);
+            }
+
+            // Every record is also an event, i.e. counts as 1 regardless of its values.
+            // Explicitly mapped fields of the same name are rejected up front, but
+            // wildcard fields (or metric names) can only be dropped here.
+            if values.insert(self.event_series.clone(), 1.0).is_some()
+                && self.verbose
+                && !self.event_clash_reported
+            {
+                eprintln!(
+                    "record series '{}' clashes with the event series (see --events) and is dropped",
+                    self.event_series
+                );
+                self.event_clash_reported = true;
+            }
+
+            if let Err(e) = self.guard.check_labels(&mut labels) {
+                self.failure = Some(e);
+                return;
+            }
+
+            let mut samples: Vec<_> = values
+                .into_iter()
+                .map(|(name, value)| Sample::new(name, value, timestamp, labels.clone()))
+                .collect();
+            for bucket in record.buckets() {
+                let mut labels = labels.clone();
+                labels.insert("le".to_owned(), bucket.le.clone());
+                samples.push(Sample::new(
+                    bucket.name.clone(),
+                    bucket.count,
+                    timestamp,
+                    labels,
+                ));
+            }
+
+            for mut sample in samples {
+                if let Err(e) = self.guard.check_series(&mut sample.labels) {
+                    self.failure = Some(e);
+                    return;
+                }
+                let sample = Rc::new(sample);
+
+                for weak_cursor in self.cursors.iter_mut() {
+                    if let Some(cursor) = weak_cursor.upgrade() {
+                        cursor.buffer.borrow_mut().push_front(sample.clone());
                     }
                 }
+            }
+        }
+    }
+
+    /// Next timestamped record, in the timestamp order if reordering is on.
+    fn next_record(&mut self) -> Option<Record> {
+        loop {
+            if let Some(record) = self.reorder.as_mut().and_then(|r| r.pop_ready()) {
+                return Some(record);
+            }
+
+            // TODO: propagate errors.
+            match self.records.next() {
+                Some(Ok(record)) if record.timestamp().is_some() => match self.reorder {
+                    Some(ref mut reorder) => reorder.push(record),
+                    None => return Some(record),
+                },
                 Some(Err(e)) if self.verbose => {
                     eprintln!("{}", e);
                 }
-                None => break,
+                None => {
+                    let reorder = self.reorder.as_mut()?;
+                    let record = reorder.pop();
+                    if record.is_none() && self.verbose {
+                        reorder.report_late();
+                    }
+                    return record;
+                }
                 _ => (),
             }
         }
     }
 }
 
+/// Holds records back until it's unlikely that an older one is still
+/// to come, i.e. until a record `max_lateness` newer shows up. Records
+/// older than the ones already released are dropped.
+struct ReorderBuffer {
+    max_lateness: Duration,
+    pending: BinaryHeap<Reverse<PendingRecord>>,
+    seq: usize,
+    newest: Timestamp,
+    released: Option<Timestamp>,
+    late: usize,
+}
+
+impl ReorderBuffer {
+    fn new(max_lateness: Duration) -> Self {
+        Self {
+            max_lateness,
+            pending: BinaryHeap::new(),
+            seq: 0,
+            newest: Timestamp::MIN,
+            released: None,
+            late: 0,
+        }
+    }
+
+    fn push(&mut self, record: Record) {
+        let timestamp = record
+            .timestamp()
+            .expect("records without timestamps are skipped");
+        if timestamp < self.released.unwrap_or(Timestamp::MIN) {
+            self.late += 1;
+            return;
+        }
+
+        self.newest = self.newest.max(timestamp);
+        self.seq += 1;
+        self.pending.push(Reverse(PendingRecord {
+            timestamp,
+            seq: self.seq,
+            record,
+        }));
+    }
+
+    fn pop_ready(&mut self) -> Option<Record> {
+        let Reverse(oldest) = self.pending.peek()?;
+        if oldest.timestamp.add(self.max_lateness) > self.newest {
+            return None;
+        }
+        self.pop()
+    }
+
+    fn pop(&mut self) -> Option<Record> {
+        let Reverse(oldest) = self.pending.pop()?;
+        self.released = Some(oldest.timestamp);
+        Some(oldest.record)
+    }
+
+    fn report_late(&mut self) {
+        if self.late > 0 {
+            eprintln!(
+                "{} record(s) arrived later than --max-lateness allows and were dropped",
+                self.late
+            );
+            self.late = 0;
+        }
+    }
+}
+
+struct PendingRecord {
+    timestamp: Timestamp,
+    seq: usize,
+    record: Record,
+}
+
+impl PartialEq for PendingRecord {
+    fn eq(&self, other: &Self) -> bool {
+        (self.timestamp, self.seq) == (other.timestamp, other.seq)
+    }
+}
+
+impl Eq for PendingRecord {}
+
+impl PartialOrd for PendingRecord {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for PendingRecord {
+    // Same timestamps keep the input order.
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.timestamp, self.seq).cmp(&(other.timestamp, other.seq))
+    }
+}
+
 pub struct Cursor {
     reader: Rc<RefCell<SampleReader>>,
     buffer: RefCell<VecDeque<Rc<Sample>>>,
@@ -127,7 +328,7 @@ impl Cursor {
     }
 
     pub fn read(&self) -> Option<Rc<Sample>> {
-        if self.buffer.borrow().len() == 0 {
+        if self.buffer.borrow().is_empty() {
             self.reader.borrow_mut().refill_cursors();
         }
         self.buffer.borrow_mut().pop_back()
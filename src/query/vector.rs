@@ -48,10 +48,7 @@ impl VectorSelectorEvaluator {
 
     fn next_sample(&self) -> Option<Rc<Sample>> {
         loop {
-            let sample = match self.cursor.read() {
-                Some(s) => s,
-                None => return None, // drained input
-            };
+            let sample = self.cursor.read()?; // None means drained input
 
             if self
                 .selector
@@ -1,17 +1,22 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
 use std::time::Duration;
 
 use crate::error::{Error, Result};
 use crate::format::{Formatter, HumanReadableFormatter, JSONFormatter, PromApiFormatter, Value};
+use crate::input::{Line, Progress};
+use crate::model::MetricName;
 use crate::output::Writer;
 use crate::parse::{
-    Decoder, DecodingStrategy, JSONDecodingStrategy, Mapper, RegexDecodingStrategy,
+    Clock, Decoder, DecodingStrategy, Deduplicator, JSONDecodingStrategy, LineDecodingStrategy,
+    Mapper, MappingStrategy, ParallelMapping, RedactingStrategy, RegexDecodingStrategy,
+    TimestampProbe, TimestampSource,
 };
 use crate::program::{self, parse_program};
-use crate::query::QueryEvaluator;
-use crate::utils::time::TimeRange;
+use crate::query::{CardinalityLimits, QueryEvaluator, SampleOptions};
+use crate::utils::time::{TimeRange, Timezone};
 
-type LineIter = Box<dyn std::iter::Iterator<Item = Result<(usize, Vec<u8>)>>>;
+type LineIter = Box<dyn std::iter::Iterator<Item = Result<Line>> + Send>;
 
 pub struct RunnerOptions {
     verbose: bool,
@@ -19,6 +24,12 @@ pub struct RunnerOptions {
     range: Option<TimeRange>,
     interval: Option<Duration>,
     lookback: Option<Duration>,
+    samples: SampleOptions,
+    timezone: Option<Timezone>,
+    year: Option<i32>,
+    timestamps: Option<TimestampSource>,
+    jobs: usize,
+    progress: Option<Progress>,
 }
 
 impl RunnerOptions {
@@ -35,14 +46,74 @@ impl RunnerOptions {
             range,
             interval,
             lookback,
+            samples: SampleOptions::default(),
+            timezone: None,
+            year: None,
+            timestamps: None,
+            jobs: 1,
+            progress: None,
         }
     }
+
+    /// Number of threads decoding and mapping lines. If greater than 1,
+    /// lines are read in batches and processed in parallel.
+    pub fn with_jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs;
+        self
+    }
+
+    /// Lets queries tolerate records out of timestamp order by up to the given duration.
+    /// Records arriving even later are dropped.
+    pub fn with_max_lateness(mut self, max_lateness: Duration) -> Self {
+        self.samples.max_lateness = Some(max_lateness);
+        self
+    }
+
+    /// Name of the series counting records, __events__ by default.
+    pub fn with_event_series(mut self, event_series: MetricName) -> Self {
+        self.samples.event_series = event_series;
+        self
+    }
+
+    /// Limits on the number of distinct label values and series queries deal with.
+    pub fn with_cardinality_limits(mut self, limits: CardinalityLimits) -> Self {
+        self.samples.cardinality = limits;
+        self
+    }
+
+    /// Counts the input lines fully processed, e.g. to checkpoint the input
+    /// offset. Lines read ahead but not processed yet aren't counted.
+    pub fn with_progress(mut self, progress: Progress) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    /// Source of timestamps for records that have none.
+    pub fn with_timestamp_source(mut self, timestamps: TimestampSource) -> Self {
+        self.timestamps = Some(timestamps);
+        self
+    }
+
+    /// Timezone of mapped timestamps lacking both a UTC offset and a `tz` clause.
+    pub fn with_timezone(mut self, timezone: Timezone) -> Self {
+        self.timezone = Some(timezone);
+        self
+    }
+
+    /// Year of mapped timestamps lacking one (the current one by default).
+    pub fn with_year(mut self, year: i32) -> Self {
+        self.year = Some(year);
+        self
+    }
 }
 
 pub struct Runner {
     producer: Producer,
     consumer: Consumer,
     verbose: bool,
+    /// Number of records dropped by the dedup stage (if any).
+    duplicates: Option<Rc<Cell<usize>>>,
+    progress: Option<Progress>,
 }
 
 impl Runner {
@@ -58,15 +129,45 @@ impl Runner {
             range,
             interval,
             lookback,
+            samples,
+            timezone,
+            year,
+            timestamps,
+            jobs,
+            progress,
         } = options;
 
         let ast = parse_program(program)?;
 
-        let decoding: Box<dyn DecodingStrategy> = match ast.decoder {
-            program::Decoder::JSON => Box::new(JSONDecodingStrategy::default()),
-            program::Decoder::Regex { regex } => Box::new(RegexDecodingStrategy::new(&regex)?),
+        let mappers: Vec<&program::Mapper> = match (&ast.decoder, &ast.mapper) {
+            (program::Decoder::Switch(arms), _) => arms.iter().map(|arm| &arm.mapper).collect(),
+            (_, mapper) => mapper.iter().collect(),
+        };
+
+        // Mined templates depend on the order in which lines are mapped.
+        let jobs = if mappers.iter().any(|m| m.mines_templates()) {
+            1
+        } else {
+            jobs
+        };
+
+        if ast.query.is_some()
+            && mappers
+                .iter()
+                .flat_map(|m| m.fields.iter())
+                .any(|f| f.end_name() == samples.event_series)
+        {
+            return Err(Error::from(format!(
+                "map defines the '{}' field clashing with the event series (see --events)",
+                samples.event_series
+            )));
+        }
+
+        let (decoding, mapping) = strategies(ast.decoder, ast.mapper, timezone, year)?;
+        let decoding: Box<dyn DecodingStrategy> = match ast.redactor {
+            Some(redactor) => Box::new(RedactingStrategy::new(decoding, redactor)),
+            None => decoding,
         };
-        let decoder = Decoder::new(reader, decoding);
 
         if let Some(program::Formatter::HumanReadable) = ast.formatter {
             if interactive {
@@ -89,9 +190,51 @@ impl Runner {
 
         let range = range.unwrap_or_else(TimeRange::infinity);
 
-        let mapper = match ast.mapper {
-            Some(mapper) => Mapper::new(Box::new(decoder), mapper, Some(range)),
+        let mut duplicates = None;
+        let mapper = match mapping {
+            Some(mapping) => {
+                let mapper = if jobs > 1 {
+                    Mapper::from_records(
+                        Box::new(ParallelMapping::new(reader, decoding, mapping, jobs)),
+                        Some(range),
+                    )
+                } else {
+                    Mapper::new(
+                        Box::new(Decoder::new(reader, decoding)),
+                        mapping,
+                        Some(range),
+                    )
+                };
+                let mapper = match progress {
+                    Some(progress) => mapper.with_progress(progress),
+                    None => mapper,
+                };
+                let mapper = match timestamps {
+                    Some(source) => {
+                        mapper.with_clock(Clock::new(source, &timezone.unwrap_or_default(), year))
+                    }
+                    None => mapper,
+                };
+                let mapper = match ast.filter {
+                    Some(filter) => mapper.with_filter(filter),
+                    None => mapper,
+                };
+                match ast.dedup {
+                    Some(dedup) => {
+                        let dedup = Deduplicator::new(dedup);
+                        duplicates = Some(dedup.dropped());
+                        mapper.with_dedup(dedup)
+                    }
+                    None => mapper,
+                }
+            }
             None => {
+                if ast.filter.is_some() {
+                    return Err(Error::new("'where' without 'map' is not supported"));
+                }
+                if ast.dedup.is_some() {
+                    return Err(Error::new("'dedup' without 'map' is not supported"));
+                }
                 if ast.query.is_some() {
                     return Err(Error::new(
                         "'select' without 'map' is not supported for this parser",
@@ -99,9 +242,11 @@ impl Runner {
                 }
 
                 return Ok(Self {
-                    producer: Producer::Decoder(RefCell::new(decoder)),
+                    producer: Producer::Decoder(RefCell::new(Decoder::new(reader, decoding))),
                     consumer,
                     verbose,
+                    duplicates: None,
+                    progress,
                 });
             }
         };
@@ -113,6 +258,8 @@ impl Runner {
                     producer: Producer::Mapper(RefCell::new(mapper)),
                     consumer,
                     verbose,
+                    duplicates,
+                    progress: None,
                 });
             }
         };
@@ -123,27 +270,36 @@ impl Runner {
                 Box::new(mapper),
                 interval,
                 lookback,
+                samples,
                 range.start(),
                 verbose, // TODO: remove it when proper error propagation is in place.
             )?)),
             consumer,
             verbose,
+            duplicates,
+            progress: None,
         })
     }
 
     pub fn run(&mut self) -> Result<()> {
         loop {
             let value = match &self.producer {
-                Producer::Decoder(decoder) => match decoder.borrow_mut().next() {
-                    Some(Ok(entry)) => Value::Entry(entry),
-                    Some(Err(e)) => {
-                        if self.verbose {
-                            eprintln!("{}", e);
+                Producer::Decoder(decoder) => {
+                    let item = decoder.borrow_mut().next();
+                    if let (Some(_), Some(progress)) = (&item, &self.progress) {
+                        progress.advance();
+                    }
+                    match item {
+                        Some(Ok((entry, _))) => Value::Entry(entry),
+                        Some(Err(e)) => {
+                            if self.verbose {
+                                eprintln!("{}", e);
+                            }
+                            continue;
                         }
-                        continue;
+                        None => break,
                     }
-                    None => break,
-                },
+                }
                 Producer::Mapper(mapper) => match mapper.borrow_mut().next() {
                     Some(Ok(record)) => Value::Record(record),
                     Some(Err(e)) => {
@@ -156,21 +312,88 @@ impl Runner {
                 },
                 Producer::Querier(querier) => match querier.borrow_mut().next() {
                     Some(Ok(value)) => Value::QueryValue(value),
-                    Some(Err(e)) => {
-                        if self.verbose {
-                            eprintln!("{}", e);
-                        }
-                        continue;
-                    }
+                    Some(Err(e)) => return Err(e), // evaluation failed
                     None => break,
                 },
             };
             self.consumer.write(&value)?;
         }
+
+        if let (true, Some(duplicates)) = (self.verbose, &self.duplicates) {
+            eprintln!("dedup dropped {} duplicate record(s)", duplicates.get());
+        }
         Ok(())
     }
 }
 
+/// Builds a probe extracting timestamps from raw lines as the given
+/// program would do it. Returns None if the program doesn't map any
+/// timestamp field.
+pub fn timestamp_probe(
+    program: &str,
+    tz: Option<Timezone>,
+    year: Option<i32>,
+) -> Result<Option<TimestampProbe>> {
+    let ast = parse_program(program)?;
+
+    let has_timestamp = match (&ast.decoder, &ast.mapper) {
+        (program::Decoder::Switch(arms), _) => arms.iter().any(|arm| arm.mapper.has_timestamp()),
+        (_, Some(mapper)) => mapper.has_timestamp(),
+        (_, None) => false,
+    };
+    if !has_timestamp {
+        return Ok(None);
+    }
+
+    match strategies(ast.decoder, ast.mapper, tz, year)? {
+        (decoding, Some(mapping)) => Ok(Some(TimestampProbe::new(decoding, mapping))),
+        (_, None) => Ok(None),
+    }
+}
+
+/// Decoding and mapping strategies of the program. Switch arms do both,
+/// so the lines are passed to the mapping as is.
+fn strategies(
+    decoder: program::Decoder,
+    mapper: Option<program::Mapper>,
+    tz: Option<Timezone>,
+    year: Option<i32>,
+) -> Result<(Box<dyn DecodingStrategy>, Option<MappingStrategy>)> {
+    let with_defaults = |mut mapper: program::Mapper| {
+        if let Some(tz) = tz {
+            mapper = mapper.with_default_timezone(tz);
+        }
+        if let Some(year) = year {
+            mapper = mapper.with_default_year(year);
+        }
+        mapper
+    };
+
+    Ok(match decoder {
+        program::Decoder::JSON => (
+            Box::new(JSONDecodingStrategy::default()),
+            mapper.map(|m| MappingStrategy::new(with_defaults(m))),
+        ),
+        program::Decoder::Regex { regex } => (
+            Box::new(RegexDecodingStrategy::new(&regex)?),
+            mapper.map(|m| MappingStrategy::new(with_defaults(m))),
+        ),
+        program::Decoder::Switch(arms) => {
+            let arms = arms
+                .into_iter()
+                .map(|arm| program::SwitchArm {
+                    regex: arm.regex,
+                    mapper: with_defaults(arm.mapper),
+                })
+                .collect();
+            (
+                Box::new(LineDecodingStrategy::default()),
+                Some(MappingStrategy::switch(arms)?),
+            )
+        }
+    })
+}
+
 enum Producer {
     Decoder(RefCell<Decoder>),
     Mapper(RefCell<Mapper>),
@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+
+use super::hash::fnv1a;
+
+/// Placeholder for the variable parts of messages.
+pub const WILDCARD: &str = "<*>";
+
+pub const DEFAULT_SIMILARITY: f64 = 0.4;
+
+/// Bounds the memory a stream of ever new messages takes.
+pub const DEFAULT_MAX_TEMPLATES: usize = 10_000;
+
+// Number of leading tokens messages are grouped by (besides the token count).
+const PREFIX_LEN: usize = 1;
+
+/// Online log template miner (a simplified Drain, see "Drain: An Online Log
+/// Parsing Approach with Fixed Depth Tree" by P. He et al.).
+///
+/// Messages are tokenized by whitespace, and tokens with digits are
+/// considered variable right away. Messages are grouped by the token count
+/// and the first tokens, and within a group, a message joins the most
+/// similar template (the share of the same tokens at the same positions)
+/// if it's similar enough. Otherwise, it starts a new template. Joining
+/// turns the mismatching tokens of the template into wildcards. When there
+/// are too many templates, the least recently used one is forgotten.
+pub struct TemplateMiner {
+    similarity: f64,
+    max_templates: usize,
+    groups: HashMap<Vec<String>, Vec<Template>>,
+    count: usize,
+    /// Number of messages added so far.
+    clock: u64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Template {
+    /// Stays the same while the template gets more general.
+    pub id: String,
+    tokens: Vec<String>,
+    last_used: u64,
+}
+
+impl Template {
+    pub fn pattern(&self) -> String {
+        self.tokens.join(" ")
+    }
+}
+
+impl TemplateMiner {
+    pub fn new(similarity: f64) -> Self {
+        Self {
+            similarity,
+            max_templates: DEFAULT_MAX_TEMPLATES,
+            groups: HashMap::new(),
+            count: 0,
+            clock: 0,
+        }
+    }
+
+    pub fn with_max_templates(mut self, max_templates: usize) -> Self {
+        assert!(max_templates > 0);
+        self.max_templates = max_templates;
+        self
+    }
+
+    pub fn similarity(&self) -> f64 {
+        self.similarity
+    }
+
+    /// Returns the (possibly updated) template of the message.
+    pub fn add(&mut self, message: &str) -> &Template {
+        let tokens: Vec<String> = message
+            .split_whitespace()
+            .map(|t| {
+                if t.bytes().any(|c| c.is_ascii_digit()) {
+                    WILDCARD.to_owned()
+                } else {
+                    t.to_owned()
+                }
+            })
+            .collect();
+
+        let mut key = vec![tokens.len().to_string()];
+        key.extend(tokens.iter().take(PREFIX_LEN).cloned());
+        self.clock += 1;
+
+        let best = self.groups.get(&key).and_then(|templates| {
+            templates
+                .iter()
+                .enumerate()
+                .map(|(i, t)| (i, similarity(&t.tokens, &tokens)))
+                // The first of the equally similar ones.
+                .fold(None, |best: Option<(usize, f64)>, (i, sim)| match best {
+                    Some((_, best_sim)) if best_sim >= sim => best,
+                    _ => Some((i, sim)),
+                })
+        });
+
+        if let Some((i, sim)) = best {
+            if sim >= self.similarity {
+                let template = &mut self.groups.get_mut(&key).unwrap()[i];
+                for (t, m) in template.tokens.iter_mut().zip(tokens.iter()) {
+                    if t != m {
+                        *t = WILDCARD.to_owned();
+                    }
+                }
+                template.last_used = self.clock;
+                return template;
+            }
+        }
+
+        if self.count == self.max_templates {
+            self.evict();
+        }
+        self.count += 1;
+
+        let templates = self.groups.entry(key).or_default();
+        templates.push(Template {
+            id: format!("{:016x}", fnv1a(tokens.join(" ").as_bytes())),
+            tokens,
+            last_used: self.clock,
+        });
+        templates.last().unwrap()
+    }
+
+    // Forgets the least recently used template.
+    fn evict(&mut self) {
+        let lru = self
+            .groups
+            .iter()
+            .flat_map(|(key, templates)| {
+                templates
+                    .iter()
+                    .enumerate()
+                    .map(move |(i, t)| (t.last_used, key, i))
+            })
+            .min()
+            .map(|(_, key, i)| (key.clone(), i));
+
+        if let Some((key, i)) = lru {
+            let templates = self.groups.get_mut(&key).unwrap();
+            templates.remove(i);
+            if templates.is_empty() {
+                self.groups.remove(&key);
+            }
+            self.count -= 1;
+        }
+    }
+}
+
+// Share of the non-wildcard template tokens found at the same positions.
+fn similarity(template: &[String], tokens: &[String]) -> f64 {
+    if template.is_empty() {
+        return 1.0;
+    }
+    let same = template
+        .iter()
+        .zip(tokens.iter())
+        .filter(|(t, m)| t != &WILDCARD && t == m)
+        .count();
+    same as f64 / template.len() as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_template_miner() {
+        let mut miner = TemplateMiner::new(DEFAULT_SIMILARITY);
+
+        #[rustfmt::skip]
+        let tests = [
+            ("connected to 10.0.0.1 in 5ms", "connected to <*> in <*>"),
+            ("user alice logged in", "user alice logged in"),
+            ("user bob logged in", "user <*> logged in"),
+            ("connected to 10.0.0.2 in 7ms", "connected to <*> in <*>"),
+            ("user carol logged out", "user <*> logged <*>"),
+            ("user dave  logged   in", "user <*> logged <*>"),
+            ("disk full", "disk full"),
+            ("", ""),
+        ];
+
+        let mut ids = vec![];
+        for (input, expected) in tests.iter() {
+            let template = miner.add(input);
+            assert_eq!(*expected, template.pattern(), "while mining {}", input);
+            ids.push(template.id.clone());
+        }
+
+        // Templates keep their IDs while getting more general.
+        assert_eq!(ids[0], ids[3]);
+        assert_eq!(ids[1], ids[2]);
+        assert_eq!(ids[1], ids[5]);
+        assert_ne!(ids[0], ids[1]);
+        assert_ne!(ids[1], ids[6]);
+    }
+
+    #[test]
+    fn test_max_templates() {
+        let mut miner = TemplateMiner::new(DEFAULT_SIMILARITY).with_max_templates(2);
+
+        miner.add("disk full");
+        miner.add("user alice logged in");
+        miner.add("disk full");
+        // Forgets the "user" template, used less recently than the "disk" one.
+        assert_eq!("cache miss", miner.add("cache miss").pattern());
+        assert_eq!(
+            "user bob logged in",
+            miner.add("user bob logged in").pattern()
+        );
+        assert_eq!("cache miss", miner.add("cache miss").pattern());
+        assert_eq!(2, miner.count);
+    }
+}
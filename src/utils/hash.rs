@@ -0,0 +1,7 @@
+/// 64-bit FNV-1a. Unlike DefaultHasher, it's stable across platforms and
+/// Rust versions, so the hashes can be persisted or shown to users.
+pub fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, b| {
+        (hash ^ *b as u64).wrapping_mul(0x100000001b3)
+    })
+}
@@ -1,2 +1,7 @@
+pub mod drain;
+pub mod hash;
+pub mod net;
 pub mod parse;
+pub mod redact;
+pub mod route;
 pub mod time;
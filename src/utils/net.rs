@@ -0,0 +1,198 @@
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::Path;
+
+use crate::error::{Error, Result};
+
+/// Parses an IP address, possibly with a port (203.0.113.42:8080, [2001:db8::1]:8080).
+pub fn parse_ip(s: &str) -> Option<IpAddr> {
+    let s = s.trim();
+    match s.parse::<IpAddr>() {
+        Ok(ip) => Some(ip),
+        Err(_) => s.parse::<SocketAddr>().ok().map(|addr| addr.ip()),
+    }
+}
+
+/// IPv4 or IPv6 network, e.g. 10.0.0.0/8 or 2001:db8::/32.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    /// The network of the given prefix length the address belongs to.
+    /// The prefix length is capped at the address length.
+    pub fn of(ip: IpAddr, prefix: u8) -> Self {
+        match ip {
+            IpAddr::V4(ip) => {
+                let prefix = prefix.min(32);
+                let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+                Self {
+                    addr: IpAddr::V4(Ipv4Addr::from(u32::from(ip) & mask)),
+                    prefix,
+                }
+            }
+            IpAddr::V6(ip) => {
+                let prefix = prefix.min(128);
+                let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+                Self {
+                    addr: IpAddr::V6(Ipv6Addr::from(u128::from(ip) & mask)),
+                    prefix,
+                }
+            }
+        }
+    }
+
+    pub fn addr(&self) -> IpAddr {
+        self.addr
+    }
+
+    pub fn prefix(&self) -> u8 {
+        self.prefix
+    }
+
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        self.addr.is_ipv4() == ip.is_ipv4() && Cidr::of(*ip, self.prefix).addr == self.addr
+    }
+}
+
+impl std::str::FromStr for Cidr {
+    type Err = Error;
+
+    /// A plain address is a single-address network (/32 or /128).
+    fn from_str(s: &str) -> Result<Self> {
+        let (addr, prefix) = match s.trim().split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s.trim(), None),
+        };
+        let addr = addr
+            .parse::<IpAddr>()
+            .map_err(|_| Error::from(format!("bad CIDR '{}': invalid address", s)))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix.map(str::parse::<u8>) {
+            None => max,
+            Some(Ok(prefix)) if prefix <= max => prefix,
+            Some(_) => {
+                return Err(Error::from(format!(
+                    "bad CIDR '{}': prefix length must be 0..{}",
+                    s, max
+                )))
+            }
+        };
+        Ok(Cidr::of(addr, prefix))
+    }
+}
+
+impl std::fmt::Display for Cidr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+/// CIDR-to-name mapping (e.g. subnets to datacenters). The most specific
+/// network wins.
+#[derive(Debug, PartialEq)]
+pub struct CidrTable {
+    entries: Vec<(Cidr, String)>,
+}
+
+impl CidrTable {
+    /// Reads `<cidr>,<name>` lines. Empty lines, #-comments, and a header are skipped.
+    pub fn from_csv<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)
+            .map_err(|e| Error::from((format!("couldn't read {}", path.display()), e)))?;
+
+        let mut entries = vec![];
+        for (i, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (cidr, name) = line.split_once(',').unwrap_or((line, ""));
+            match cidr.parse::<Cidr>() {
+                Ok(cidr) => entries.push((cidr, name.trim().trim_matches('"').to_owned())),
+                Err(_) if entries.is_empty() && i == 0 => continue, // header
+                Err(e) => return Err(Error::from(format!("{}:{}: {}", path.display(), i + 1, e))),
+            }
+        }
+
+        entries.sort_by_key(|(cidr, _)| std::cmp::Reverse(cidr.prefix));
+        Ok(Self { entries })
+    }
+
+    pub fn lookup(&self, ip: &IpAddr) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(cidr, _)| cidr.contains(ip))
+            .map(|(_, name)| name.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        parse_ip(s).unwrap()
+    }
+
+    #[test]
+    fn test_cidr() -> Result<()> {
+        #[rustfmt::skip]
+        let tests = [
+            ("10.0.0.0/8", "10.1.2.3", true),
+            ("10.0.0.0/8", "11.1.2.3", false),
+            ("10.1.2.3/8", "10.200.0.1:443", true),
+            ("203.0.113.42", "203.0.113.42", true),
+            ("203.0.113.42", "203.0.113.43", false),
+            ("0.0.0.0/0", "8.8.8.8", true),
+            ("0.0.0.0/0", "::1", false),
+            ("2001:db8::/32", "2001:db8:1:2::1", true),
+            ("2001:db8::/32", "[2001:db9::1]:443", false),
+        ];
+        for (cidr, addr, expected) in tests.iter() {
+            let cidr = cidr.parse::<Cidr>()?;
+            assert_eq!(*expected, cidr.contains(&ip(addr)), "{} in {}", addr, cidr);
+        }
+
+        assert_eq!("10.0.0.0/8", "10.1.2.3/8".parse::<Cidr>()?.to_string());
+        assert_eq!(
+            "2001:db8:1::/48",
+            Cidr::of(ip("2001:db8:1:2::1"), 48).to_string()
+        );
+        assert_eq!("1.2.3.4/32", Cidr::of(ip("1.2.3.4"), 64).to_string());
+
+        for input in &[
+            "10.0.0.0/33",
+            "10.0.0/8",
+            "::/129",
+            "example.com",
+            "10.0.0.0/",
+        ] {
+            assert!(input.parse::<Cidr>().is_err(), "while parsing {}", input);
+        }
+        assert_eq!(None, parse_ip("example.com:80"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_cidr_table() -> std::result::Result<(), Box<dyn std::error::Error>> {
+        let path = std::env::temp_dir().join(format!("pq-cidr-table-{}.csv", std::process::id()));
+        fs::write(
+            &path,
+            "cidr,name\n# comment\n10.0.0.0/8,internal\n10.1.0.0/16, dc-1\n\n2001:db8::/32,\"dc-2\"\n",
+        )?;
+        let table = CidrTable::from_csv(&path);
+        fs::remove_file(&path)?;
+        let table = table?;
+
+        assert_eq!(Some("dc-1"), table.lookup(&ip("10.1.2.3")));
+        assert_eq!(Some("internal"), table.lookup(&ip("10.2.0.1")));
+        assert_eq!(Some("dc-2"), table.lookup(&ip("2001:db8::1")));
+        assert_eq!(None, table.lookup(&ip("192.168.0.1")));
+        Ok(())
+    }
+}
@@ -0,0 +1,85 @@
+use nom::{
+    branch::alt,
+    bytes::complete::tag_no_case,
+    character::complete::space0,
+    combinator::{opt, value},
+    sequence::preceded,
+};
+
+use super::number::decimal_literal;
+use super::result::{IResult, ParseError, Span};
+use crate::error::{Error, Result};
+
+/// Parse a whole string as a byte size: `512`, `100B`, `10KB`, `1.2MiB`.
+/// kB, MB, etc. are decimal units (powers of 1000) while KiB, MiB, etc.
+/// are binary ones (powers of 1024).
+pub fn parse_bytes(s: &str) -> Result<f64> {
+    match bytes(Span::new(s)) {
+        Ok((rest, n)) if rest.is_empty() => Ok(n),
+        Ok((rest, _)) => Err(Error::from(
+            ParseError::partial("byte size", "EOF", rest).message(),
+        )),
+        Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => Err(Error::from(e.message())),
+        _ => unreachable!(),
+    }
+}
+
+pub fn bytes(input: Span) -> IResult<f64> {
+    let (rest, n) = decimal_literal(input)?;
+    let (rest, multiplier) = opt(preceded(space0, bytes_unit))(rest)?;
+    Ok((
+        rest,
+        (*n).parse::<f64>().unwrap() * multiplier.unwrap_or(1.0),
+    ))
+}
+
+fn bytes_unit(input: Span) -> IResult<f64> {
+    const KI: f64 = 1024.0;
+
+    // Longer units go first.
+    alt((
+        value(KI, tag_no_case("KiB")),
+        value(KI * KI, tag_no_case("MiB")),
+        value(KI * KI * KI, tag_no_case("GiB")),
+        value(KI * KI * KI * KI, tag_no_case("TiB")),
+        value(KI * KI * KI * KI * KI, tag_no_case("PiB")),
+        value(1e3, tag_no_case("kB")),
+        value(1e6, tag_no_case("MB")),
+        value(1e9, tag_no_case("GB")),
+        value(1e12, tag_no_case("TB")),
+        value(1e15, tag_no_case("PB")),
+        value(1.0, tag_no_case("B")),
+    ))(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_bytes() {
+        #[rustfmt::skip]
+        let tests = [
+            ("512", Ok(512.0)),
+            ("100B", Ok(100.0)),
+            ("10KB", Ok(10_000.0)),
+            ("10 kB", Ok(10_000.0)),
+            ("1.5KiB", Ok(1536.0)),
+            ("1.25MiB", Ok(1_310_720.0)),
+            ("2GB", Ok(2e9)),
+            ("1TiB", Ok(1_099_511_627_776.0)),
+            ("10XB", Err(())),
+            ("KB", Err(())),
+            ("-1B", Err(())),
+        ];
+
+        for (input, expected) in &tests {
+            assert_eq!(
+                *expected,
+                parse_bytes(input).map_err(|_| ()),
+                "while parsing {}",
+                input
+            );
+        }
+    }
+}
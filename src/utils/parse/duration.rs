@@ -1,8 +1,13 @@
 use std::convert::TryFrom;
 use std::time::Duration;
 
-use nom::{branch::alt, bytes::complete::tag, character::complete::digit1};
+use nom::{
+    branch::alt,
+    bytes::complete::tag,
+    character::complete::{digit1, space0},
+};
 
+use super::number::decimal_literal;
 use super::result::{IResult, ParseError, Span};
 use crate::error::{Error, Result};
 
@@ -14,12 +19,27 @@ pub fn parse_duration(s: &str) -> Result<Duration> {
     }
 }
 
+/// Parse a whole string as a duration that can also be zero
+/// (e.g. a latency field of a log record). Unlike in programs,
+/// fractions (`12.5ms`), microseconds and nanoseconds (`350us`),
+/// and a space before the unit (`12.5 ms`) are allowed.
+pub fn parse_duration_value(s: &str) -> Result<Duration> {
+    match duration_inner(Span::new(s), Unit::Year, true) {
+        Ok((rest, d)) if rest.is_empty() => Ok(d),
+        Ok((rest, _)) => Err(Error::from(
+            ParseError::partial("duration", "EOF", rest).message(),
+        )),
+        Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => Err(Error::from(e.message())),
+        _ => unreachable!(),
+    }
+}
+
 /// Parse Go-like duration string: `2s`, `1y3w5d7h9m`.
 /// - Only positive durations.
 /// - No fractional units.
 /// - Units are always ordered from longest to shortest.
 pub fn duration(input: Span) -> IResult<Duration> {
-    let (rest, duration) = duration_inner(input, Unit::Year)?;
+    let (rest, duration) = duration_inner(input, Unit::Year, false)?;
 
     if duration.eq(&Duration::from_millis(0)) {
         return Err(nom::Err::Failure(ParseError::new(
@@ -32,33 +52,39 @@ pub fn duration(input: Span) -> IResult<Duration> {
 }
 
 enum Unit {
-    Millisecond,
-    Second, // 1000 milliseconds
-    Minute, // 60 seconds
-    Hour,   // 60 minutes
-    Day,    // 24 hours
-    Week,   // 7 days
-    Year,   // 365 days, always
+    Nanosecond,
+    Microsecond, // 1000 nanoseconds
+    Millisecond, // 1000 microseconds
+    Second,      // 1000 milliseconds
+    Minute,      // 60 seconds
+    Hour,        // 60 minutes
+    Day,         // 24 hours
+    Week,        // 7 days
+    Year,        // 365 days, always
 }
 
 impl Unit {
-    fn milliseconds(&self) -> u64 {
+    fn nanoseconds(&self) -> u64 {
         use Unit::*;
         match self {
-            Millisecond => 1,
-            Second => 1000,
-            Minute => 60 * 1000,
-            Hour => 60 * 60 * 1000,
-            Day => 24 * 60 * 60 * 1000,
-            Week => 7 * 24 * 60 * 60 * 1000,
-            Year => 365 * 24 * 60 * 60 * 1000,
+            Nanosecond => 1,
+            Microsecond => 1000,
+            Millisecond => 1000 * 1000,
+            Second => 1000 * 1000 * 1000,
+            Minute => 60 * Second.nanoseconds(),
+            Hour => 60 * 60 * Second.nanoseconds(),
+            Day => 24 * 60 * 60 * Second.nanoseconds(),
+            Week => 7 * 24 * 60 * 60 * Second.nanoseconds(),
+            Year => 365 * 24 * 60 * 60 * Second.nanoseconds(),
         }
     }
 
     fn descendant(&self) -> Option<Self> {
         use Unit::*;
         match self {
-            Millisecond => None,
+            Nanosecond => None,
+            Microsecond => Some(Nanosecond),
+            Millisecond => Some(Microsecond),
             Second => Some(Millisecond),
             Minute => Some(Second),
             Day => Some(Hour),
@@ -83,16 +109,29 @@ impl std::convert::TryFrom<&str> for Unit {
             "m" => Ok(Minute),
             "s" => Ok(Second),
             "ms" => Ok(Millisecond),
+            "us" | "µs" | "μs" => Ok(Microsecond),
+            "ns" => Ok(Nanosecond),
             _ => Err(Error::new("Unknown duration unit")),
         }
     }
 }
 
-fn duration_inner(input: Span, max_allowed_unit: Unit) -> IResult<Duration> {
-    let (rest, multiplier) = digit1(input)?;
+// The extended grammar is the one of parse_duration_value().
+fn duration_inner(input: Span, max_allowed_unit: Unit, extended: bool) -> IResult<Duration> {
+    let (rest, multiplier) = if extended {
+        let (rest, multiplier) = decimal_literal(input)?;
+        let (rest, _) = space0(rest)?;
+        (rest, multiplier)
+    } else {
+        digit1(input)?
+    };
 
     let (rest, unit) = alt((
         tag("ms"),
+        tag("us"),
+        tag("µs"), // micro sign
+        tag("μs"), // Greek mu
+        tag("ns"),
         tag("s"),
         tag("m"),
         tag("h"),
@@ -102,20 +141,39 @@ fn duration_inner(input: Span, max_allowed_unit: Unit) -> IResult<Duration> {
     ))(rest)?;
 
     let unit = Unit::try_from(*unit).unwrap();
-    if unit.milliseconds() > max_allowed_unit.milliseconds() {
+    if !extended && unit.nanoseconds() < Unit::Millisecond.nanoseconds() {
+        return Err(nom::Err::Error(ParseError::new(
+            "sub-millisecond durations are not supported".to_owned(),
+            input,
+        )));
+    }
+    if unit.nanoseconds() > max_allowed_unit.nanoseconds() {
         return Err(nom::Err::Failure(ParseError::new(
             "invalid duration literal".to_owned(),
             input,
         )));
     }
 
-    let multiplier = multiplier.parse::<u32>().unwrap();
-    let duration = Duration::from_millis(unit.milliseconds())
-        .checked_mul(multiplier)
-        .expect("duration overflow occurred");
+    let duration = match multiplier.parse::<u64>() {
+        Ok(multiplier) => multiplier.checked_mul(unit.nanoseconds()),
+        // Fractions of nanoseconds are rounded.
+        Err(_) => {
+            Some((multiplier.parse::<f64>().unwrap() * unit.nanoseconds() as f64).round() as u64)
+        }
+    }
+    .map(Duration::from_nanos);
+    let duration = match duration {
+        Some(duration) => duration,
+        None => {
+            return Err(nom::Err::Failure(ParseError::new(
+                "duration is too long".to_owned(),
+                input,
+            )))
+        }
+    };
 
     if let Some(next_unit) = unit.descendant() {
-        let (rest, more_duration) = match duration_inner(rest, next_unit) {
+        let (rest, more_duration) = match duration_inner(rest, next_unit, extended) {
             Ok((rest, more_duration)) => (rest, more_duration),
             Err(nom::Err::Error(_)) => (rest, Duration::from_millis(0)),
             Err(e) => return Err(e),
@@ -164,6 +222,33 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_duration_value() {
+        #[rustfmt::skip]
+        let tests = [
+            ("0s", Ok(Duration::from_secs(0))),
+            ("1m3s", Ok(Duration::from_millis(MINUTE + 3 * SECOND))),
+            ("12.5ms", Ok(Duration::from_micros(12500))),
+            ("12.5 ms", Ok(Duration::from_micros(12500))),
+            ("1.5h", Ok(Duration::from_millis(90 * MINUTE))),
+            ("1.5us", Ok(Duration::from_nanos(1500))),
+            ("350µs", Ok(Duration::from_micros(350))),
+            ("1ms1us1ns", Ok(Duration::from_nanos(1_001_001))),
+            ("12.5", Err(())),
+            ("12.5 ms ", Err(())),
+            ("1.s", Err(())),
+        ];
+
+        for (input, expected) in &tests {
+            assert_eq!(
+                *expected,
+                parse_duration_value(input).map_err(|_| ()),
+                "while parsing {}",
+                input
+            );
+        }
+    }
+
     #[test]
     fn test_invalid_duration() {
         #[rustfmt::skip]
@@ -172,6 +257,8 @@ mod tests {
             "0",
             "0ms",
             "1ns",
+            "350us",
+            "12.5ms",
             "0s0ms",
             "10m2h",
         ];
@@ -1,3 +1,4 @@
+mod bytes;
 mod common;
 mod duration;
 mod number;
@@ -5,6 +6,7 @@ mod result;
 mod string;
 
 // Re-export
+pub use self::bytes::*;
 pub use common::*;
 pub use duration::*;
 pub use number::*;
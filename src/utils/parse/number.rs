@@ -1,4 +1,9 @@
-use nom::number::complete::double;
+use nom::{
+    character::complete::{char, digit1},
+    combinator::{opt, recognize},
+    number::complete::double,
+    sequence::{pair, preceded},
+};
 
 use super::result::{IResult, Span};
 
@@ -7,6 +12,12 @@ pub fn number_literal(input: Span) -> IResult<f64> {
     Ok((rest, n))
 }
 
+/// Unsigned number like `12` or `12.5` (no exponent, so it can be
+/// directly followed by a unit like `12.5EiB`).
+pub fn decimal_literal(input: Span) -> IResult<Span> {
+    recognize(pair(digit1, opt(preceded(char('.'), digit1))))(input)
+}
+
 #[cfg(test)]
 mod tests {
     use super::super::result::ParseError;
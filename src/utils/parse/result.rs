@@ -33,7 +33,7 @@ impl<'a> ParseError<'a> {
     }
 
     #[inline]
-    pub fn span(&self) -> &Span {
+    pub fn span(&self) -> &Span<'a> {
         &self.span
     }
 
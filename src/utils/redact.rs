@@ -0,0 +1,72 @@
+use std::net::IpAddr;
+
+use super::net::{parse_ip, Cidr};
+
+/// Hex-encoded HMAC-SHA256 truncated to 64 bits (same length as `hash`).
+/// Unlike plain hashing, it can't be reversed by hashing all the possible
+/// values (e.g. all the IPv4 addresses) without knowing the key.
+pub fn hmac(data: &[u8], key: &[u8]) -> String {
+    hmac_sha256::HMAC::mac(data, key)[..8]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// 203.0.113.42 -> 203.0.113.0 (/24), 2001:db8:1:2::1 -> 2001:db8:1:: (/48).
+/// Ports (203.0.113.42:8080, [2001:db8::1]:8080) are dropped too.
+/// Returns None for non-IP values.
+pub fn truncate_ip(s: &str, v4_prefix: u8, v6_prefix: u8) -> Option<String> {
+    let ip = parse_ip(s)?;
+    let prefix = match ip {
+        IpAddr::V4(_) => v4_prefix,
+        IpAddr::V6(_) => v6_prefix,
+    };
+    Some(Cidr::of(ip, prefix).addr().to_string())
+}
+
+/// john.doe@example.com -> j***@example.com. Values that don't look
+/// like emails are masked completely.
+pub fn mask_email(s: &str) -> String {
+    match s.trim().rsplit_once('@') {
+        Some((user, domain)) if !domain.is_empty() => match user.chars().next() {
+            Some(first) => format!("{}***@{}", first, domain),
+            None => format!("***@{}", domain),
+        },
+        _ => "***".to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redact() {
+        #[rustfmt::skip]
+        let tests = [
+            ("203.0.113.42", 24, 48, Some("203.0.113.0")),
+            ("203.0.113.42:8080", 16, 48, Some("203.0.0.0")),
+            ("203.0.113.42", 0, 48, Some("0.0.0.0")),
+            ("203.0.113.42", 32, 48, Some("203.0.113.42")),
+            ("2001:db8:1:2::1", 24, 48, Some("2001:db8:1::")),
+            ("[2001:db8:1:2::1]:443", 24, 64, Some("2001:db8:1:2::")),
+            ("example.com", 24, 48, None),
+        ];
+        for (input, v4_prefix, v6_prefix, expected) in tests.iter() {
+            assert_eq!(
+                expected.map(str::to_owned),
+                truncate_ip(input, *v4_prefix, *v6_prefix),
+                "while truncating {}",
+                input
+            );
+        }
+
+        assert_eq!("j***@example.com", mask_email("john.doe@example.com"));
+        assert_eq!("***@example.com", mask_email("@example.com"));
+        assert_eq!("***", mask_email("john.doe"));
+
+        assert_eq!(16, hmac(b"42", b"secret").len());
+        assert_eq!(hmac(b"42", b"secret"), hmac(b"42", b"secret"));
+        assert_ne!(hmac(b"42", b"secret"), hmac(b"42", b"another secret"));
+    }
+}
@@ -0,0 +1,148 @@
+use std::fs;
+
+use crate::error::{Error, Result};
+
+/// http://example.com/foo?bar#baz -> /foo
+pub fn url_path(url: &str) -> &str {
+    let path = match url.find("://") {
+        Some(pos) => {
+            let rest = &url[pos + 3..];
+            &rest[rest.find('/').unwrap_or(rest.len())..]
+        }
+        None => url,
+    };
+    &path[..path.find(['?', '#']).unwrap_or(path.len())]
+}
+
+/// Collapses URL paths into routes, e.g. `/users/123/orders/9` into a
+/// user-supplied `/users/:id/orders/:oid` template. Paths matching no
+/// template get their numeric, UUID, and hex segments replaced with
+/// `:id`, `:uuid`, and `:hex` respectively.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RouteTable {
+    templates: Vec<String>,
+}
+
+impl RouteTable {
+    /// Templates are tried in order. `:name` segments match any segment,
+    /// and a trailing `*` matches the rest of the path. `@file` reads
+    /// templates from a file, one per line.
+    pub fn new(templates: &[String]) -> Result<Self> {
+        let mut table = Self::default();
+        for template in templates {
+            match template.strip_prefix('@') {
+                Some(path) => {
+                    let content = fs::read_to_string(path)
+                        .map_err(|e| Error::from((format!("couldn't read {}", path), e)))?;
+                    for line in content.lines().map(str::trim) {
+                        if !line.is_empty() && !line.starts_with('#') {
+                            table.add(line)?;
+                        }
+                    }
+                }
+                None => table.add(template)?,
+            }
+        }
+        Ok(table)
+    }
+
+    fn add(&mut self, template: &str) -> Result<()> {
+        if !template.starts_with('/') {
+            return Err(Error::from(format!(
+                "route template must start with '/', got '{}'",
+                template
+            )));
+        }
+        self.templates.push(template.to_owned());
+        Ok(())
+    }
+
+    pub fn normalize(&self, url: &str) -> String {
+        let path = url_path(url);
+        match self.templates.iter().find(|t| matches_template(t, path)) {
+            Some(template) => template.clone(),
+            None => path
+                .split('/')
+                .map(auto_segment)
+                .collect::<Vec<_>>()
+                .join("/"),
+        }
+    }
+}
+
+fn matches_template(template: &str, path: &str) -> bool {
+    let mut path = path.split('/');
+    for expected in template.split('/') {
+        if expected == "*" {
+            return path.next().is_some_and(|s| !s.is_empty());
+        }
+        match path.next() {
+            Some(actual) if expected.starts_with(':') => {
+                if actual.is_empty() {
+                    return false;
+                }
+            }
+            Some(actual) if actual == expected => (),
+            _ => return false,
+        }
+    }
+    path.next().is_none()
+}
+
+fn auto_segment(segment: &str) -> &str {
+    if !segment.is_empty() && segment.bytes().all(|c| c.is_ascii_digit()) {
+        ":id"
+    } else if is_uuid(segment) {
+        ":uuid"
+    } else if segment.len() >= 8
+        && segment.bytes().all(|c| c.is_ascii_hexdigit())
+        && segment.bytes().any(|c| c.is_ascii_digit())
+    {
+        ":hex"
+    } else {
+        segment
+    }
+}
+
+// 8-4-4-4-12 hex digits.
+fn is_uuid(s: &str) -> bool {
+    let groups: Vec<_> = s.split('-').map(str::len).collect();
+    groups == [8, 4, 4, 4, 12] && s.bytes().all(|c| c == b'-' || c.is_ascii_hexdigit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_route_table() -> Result<()> {
+        let table =
+            RouteTable::new(&["/users/:id/orders/:oid".to_owned(), "/static/*".to_owned()])?;
+
+        #[rustfmt::skip]
+        let tests = [
+            ("/users/123/orders/9", "/users/:id/orders/:oid"),
+            ("https://example.com/users/alice/orders/x1?page=2", "/users/:id/orders/:oid"),
+            ("/users/123/orders/", "/users/:id/orders/"),
+            ("/users/123/orders/9/items", "/users/:id/orders/:id/items"),
+            ("/static/css/main.css", "/static/*"),
+            ("/static/", "/static/"),
+            ("/items/550e8400-e29b-41d4-a716-446655440000", "/items/:uuid"),
+            ("/commits/3f786850e387550fdab836ed7e6dc881de23001b", "/commits/:hex"),
+            ("/api/v1/feedback/deadbeef", "/api/v1/feedback/deadbeef"),
+            ("/", "/"),
+        ];
+        for (input, expected) in tests.iter() {
+            assert_eq!(
+                *expected,
+                table.normalize(input),
+                "while normalizing {}",
+                input
+            );
+        }
+
+        assert!(RouteTable::new(&["users/:id".to_owned()]).is_err());
+        assert!(RouteTable::new(&["@/no/such/file".to_owned()]).is_err());
+        Ok(())
+    }
+}
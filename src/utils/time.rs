@@ -1,7 +1,11 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
 use chrono::prelude::*;
 
+use super::parse::parse_duration;
 use crate::error::{Error, Result};
-use crate::model::Timestamp;
+use crate::model::{Timestamp, TimestampTrait};
 
 #[derive(Debug, Clone, Copy)]
 pub struct TimeRange {
@@ -35,56 +39,399 @@ impl TimeRange {
     }
 }
 
-pub fn parse_time(s: &str, format: &str) -> Result<Timestamp> {
+/// A point in time as it's written on the command line. Relative
+/// expressions can only be turned into timestamps given the current
+/// time and a timezone (to know when "today" starts).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimeExpr {
+    /// 2021-01-01T00:00:00Z, 1609459200, etc.
+    Absolute(Timestamp),
+    /// 2021-01-01 00:00:00, i.e. no UTC offset given.
+    Local(NaiveDateTime),
+    /// Jun  1 12:00:00, i.e. neither the year nor a UTC offset given
+    /// (the year is a placeholder).
+    LocalNoYear(NaiveDateTime),
+    /// now, now-30m, now+1h, or just 2h (i.e. 2 hours ago).
+    Now { offset: Duration, ahead: bool },
+    /// today, yesterday, today 09:00, yesterday 23:59:59
+    Day {
+        days_ago: u32,
+        time_of_day: Option<NaiveTime>,
+    },
+}
+
+impl TimeExpr {
+    /// Times lacking the year get the given one (the current one by default).
+    pub fn resolve(&self, now: DateTime<Utc>, tz: &Timezone, year: Option<i32>) -> Timestamp {
+        match self {
+            TimeExpr::Absolute(ts) => *ts,
+            TimeExpr::Local(dt) => tz.from_local(dt),
+            TimeExpr::LocalNoYear(dt) => {
+                let in_year = |year: i32| {
+                    // Feb 29 of a non-leap year is Mar 1.
+                    let dt = dt.with_year(year).unwrap_or_else(|| {
+                        (*dt - chrono::Duration::days(1)).with_year(year).unwrap()
+                            + chrono::Duration::days(1)
+                    });
+                    Some(tz.from_local(&dt))
+                };
+                resolve_no_year(in_year, year, now).unwrap()
+            }
+            TimeExpr::Now { offset, ahead } => {
+                let now = now.timestamp_millis();
+                if *ahead {
+                    now.add(*offset)
+                } else {
+                    now.sub(*offset)
+                }
+            }
+            TimeExpr::Day {
+                days_ago,
+                time_of_day,
+            } => {
+                let day = tz.today(now) - chrono::Duration::days(*days_ago as i64);
+                tz.from_local(
+                    &day.and_time(time_of_day.unwrap_or_else(|| NaiveTime::from_hms(0, 0, 0))),
+                )
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for TimeExpr {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+
+        if let Some(offset) = s.strip_prefix("now") {
+            return match offset.trim_start().chars().next() {
+                None => Ok(TimeExpr::Now {
+                    offset: Duration::from_millis(0),
+                    ahead: false,
+                }),
+                Some(sign @ '+') | Some(sign @ '-') => Ok(TimeExpr::Now {
+                    offset: parse_duration(offset.trim_start()[1..].trim_start())?,
+                    ahead: sign == '+',
+                }),
+                Some(_) => Err(Error::new("expected now[+-]<duration>")),
+            };
+        }
+
+        for (day, days_ago) in &[("today", 0), ("yesterday", 1)] {
+            if let Some(time_of_day) = s.strip_prefix(day) {
+                let time_of_day = match time_of_day.trim() {
+                    "" => None,
+                    t => Some(
+                        NaiveTime::parse_from_str(t, "%H:%M:%S")
+                            .or_else(|_| NaiveTime::parse_from_str(t, "%H:%M"))
+                            .map_err(|e| ("couldn't parse time of day", e))?,
+                    ),
+                };
+                return Ok(TimeExpr::Day {
+                    days_ago: *days_ago,
+                    time_of_day,
+                });
+            }
+        }
+
+        if let Some(dt) = try_parse_naive_time(s) {
+            return Ok(TimeExpr::Local(dt));
+        }
+
+        if let Some(dt) = try_parse_no_year_time(s) {
+            return Ok(TimeExpr::LocalNoYear(dt));
+        }
+
+        if let Some(ts) = try_parse_time(s, &Timezone::default()) {
+            return Ok(TimeExpr::Absolute(ts));
+        }
+
+        match parse_duration(s) {
+            Ok(offset) => Ok(TimeExpr::Now {
+                offset,
+                ahead: false,
+            }),
+            Err(_) => Err(Error::new("couldn't guess time format")),
+        }
+    }
+}
+
+/// A timezone to interpret timestamps without a UTC offset in.
+/// Named timezones come from the compiled-in tz database.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Timezone {
+    Fixed(FixedOffset),
+    Named(chrono_tz::Tz),
+}
+
+impl Default for Timezone {
+    fn default() -> Self {
+        Timezone::Fixed(FixedOffset::east(0))
+    }
+}
+
+impl Timezone {
+    /// Converts a local time to a timestamp. Ambiguous times (DST fall back)
+    /// resolve to the earlier instant, non-existent ones (DST spring forward)
+    /// are shifted forward by the gap.
+    pub fn from_local(&self, dt: &NaiveDateTime) -> Timestamp {
+        match self {
+            Timezone::Fixed(tz) => local_to_timestamp(tz, dt),
+            Timezone::Named(tz) => local_to_timestamp(tz, dt),
+        }
+    }
+
+    fn today(&self, now: DateTime<Utc>) -> NaiveDate {
+        match self {
+            Timezone::Fixed(tz) => now.with_timezone(tz).naive_local().date(),
+            Timezone::Named(tz) => now.with_timezone(tz).naive_local().date(),
+        }
+    }
+}
+
+fn local_to_timestamp<T: TimeZone>(tz: &T, dt: &NaiveDateTime) -> Timestamp {
+    if let Some(local) = tz.from_local_datetime(dt).earliest() {
+        return local.timestamp_millis();
+    }
+    // In a gap, the offset before the transition is still in effect.
+    let before = tz.offset_from_utc_datetime(&(*dt - chrono::Duration::days(1)));
+    (*dt - before.fix()).timestamp_millis()
+}
+
+/// Parses a timezone given as a UTC offset (UTC, Z, +02:00, -0530, +03)
+/// or a tz database name (Europe/Berlin, America/New_York).
+pub fn parse_timezone(s: &str) -> Result<Timezone> {
+    let s = s.trim();
+    if s.eq_ignore_ascii_case("utc") || s.eq_ignore_ascii_case("z") {
+        return Ok(Timezone::default());
+    }
+
+    let error =
+        || Error::new("timezone must be UTC, an offset like +02:00, or a name like Europe/Berlin");
+
+    let (sign, rest) = match s.chars().next() {
+        Some('+') => (1, &s[1..]),
+        Some('-') => (-1, &s[1..]),
+        _ => {
+            return s
+                .parse::<chrono_tz::Tz>()
+                .map(Timezone::Named)
+                .map_err(|_| error())
+        }
+    };
+
+    let digits: String = rest.chars().filter(|c| *c != ':').collect();
+    let (hours, minutes) = match digits.len() {
+        2 => (digits.parse::<i32>(), Ok(0)),
+        4 => (digits[..2].parse::<i32>(), digits[2..].parse::<i32>()),
+        _ => return Err(error()),
+    };
+
+    match (hours, minutes) {
+        (Ok(h), Ok(m)) if h < 24 && m < 60 => Ok(Timezone::Fixed(FixedOffset::east(
+            sign * (h * 3600 + m * 60),
+        ))),
+        _ => Err(error()),
+    }
+}
+
+/// Parses a timestamp of the given strftime-like format. Unless the
+/// format has a %z offset, the time is considered local to the timezone.
+pub fn parse_time(s: &str, format: &str, tz: &Timezone) -> Result<Timestamp> {
     if format.contains("%z") {
         Ok(DateTime::parse_from_str(s, format)
-            .map_err(|e| (Error::from(("couldn't parse timestamp", e))))?
+            .map_err(|e| Error::from(("couldn't parse timestamp", e)))?
             .timestamp_millis())
     } else {
-        Ok(NaiveDateTime::parse_from_str(s, format)
-            .map_err(|e| (Error::from(("couldn't parse timestamp", e))))?
-            .timestamp_millis())
+        Ok(tz.from_local(
+            &NaiveDateTime::parse_from_str(s, format)
+                .map_err(|e| Error::from(("couldn't parse timestamp", e)))?,
+        ))
     }
 }
 
-pub fn try_parse_time(s: &str) -> Option<Timestamp> {
-    if let Ok(dt) = DateTime::parse_from_rfc3339(s) {
-        return Some(dt.timestamp_millis());
-    }
+/// A timestamp format known to the guesser.
+#[derive(Debug, Clone, Copy)]
+enum TimeFormat {
+    Rfc3339,
+    Rfc2822,
+    /// Has a UTC offset.
+    Offset(&'static str),
+    /// Local to a timezone.
+    Naive(&'static str),
+    /// Local to a timezone and lacks the year (the current one unless given).
+    NoYear(&'static str),
+    /// Seconds, milliseconds, microseconds, or nanoseconds since the epoch.
+    Epoch,
+}
+
+#[rustfmt::skip]
+const TIME_FORMATS: &[TimeFormat] = &[
+    TimeFormat::Rfc3339,
+    TimeFormat::Rfc2822,
+    // Nginx, Apache access log
+    TimeFormat::Offset("%d/%b/%Y:%H:%M:%S %z"),
+    TimeFormat::Offset("%Y-%m-%d %H:%M:%S%.f %z"),
+    // ISO-like
+    TimeFormat::Naive("%Y-%m-%d %H:%M:%S%.f"),
+    TimeFormat::Naive("%Y-%m-%dT%H:%M:%S%.f"),
+    // log4j, Python logging
+    TimeFormat::Naive("%Y-%m-%d %H:%M:%S,%3f"),
+    // ISO week date
+    TimeFormat::Naive("%G-W%V-%uT%H:%M:%S%.f"),
+    TimeFormat::Naive("%G-W%V-%u %H:%M:%S%.f"),
+    // Go log package
+    TimeFormat::Naive("%Y/%m/%d %H:%M:%S%.f"),
+    // Apache error log
+    TimeFormat::Naive("%a %b %d %H:%M:%S%.f %Y"),
+    // BSD syslog
+    TimeFormat::NoYear("%b %e %H:%M:%S%.f"),
+    TimeFormat::Epoch,
+];
 
-    if let Ok(dt) = DateTime::parse_from_rfc2822(s) {
-        return Some(dt.timestamp_millis());
+impl TimeFormat {
+    fn parse(&self, s: &str, tz: &Timezone, year: Option<i32>) -> Option<Timestamp> {
+        match self {
+            TimeFormat::Rfc3339 => DateTime::parse_from_rfc3339(s)
+                .ok()
+                .map(|dt| dt.timestamp_millis()),
+            TimeFormat::Rfc2822 => DateTime::parse_from_rfc2822(s)
+                .ok()
+                .map(|dt| dt.timestamp_millis()),
+            TimeFormat::Offset(format) => DateTime::parse_from_str(s, format)
+                .ok()
+                .map(|dt| dt.timestamp_millis()),
+            TimeFormat::Naive(format) => NaiveDateTime::parse_from_str(s, format)
+                .ok()
+                .map(|dt| tz.from_local(&dt)),
+            TimeFormat::NoYear(format) => {
+                let format = format!("%Y {}", format);
+                let parse = |year: i32| {
+                    NaiveDateTime::parse_from_str(&format!("{} {}", year, s), &format)
+                        .ok()
+                        .map(|dt| tz.from_local(&dt))
+                };
+                resolve_no_year(parse, year, Utc::now())
+            }
+            TimeFormat::Epoch => try_parse_epoch(s),
+        }
     }
+}
 
-    // Nginx
-    if let Ok(dt) = DateTime::parse_from_str(s, "%d/%b/%Y:%H:%M:%S %z") {
-        return Some(dt.timestamp_millis());
+/// Guesses timestamp formats. Lines of the same stream rarely mix formats,
+/// so the format that matched the last time is tried first.
+#[derive(Debug, Default)]
+pub struct TimeFormatGuesser {
+    last: AtomicUsize,
+    /// Year of timestamps lacking one, e.g. of BSD syslog.
+    year: Option<i32>,
+}
+
+impl Clone for TimeFormatGuesser {
+    fn clone(&self) -> Self {
+        Self {
+            last: AtomicUsize::new(self.last.load(Ordering::Relaxed)),
+            year: self.year,
+        }
     }
+}
 
-    // ISO-like
-    if let Ok(dt) = NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S%.f") {
-        return Some(dt.timestamp_millis());
+impl TimeFormatGuesser {
+    /// Timestamps lacking the year get the given one rather than the current one.
+    pub fn with_year(year: i32) -> Self {
+        Self {
+            year: Some(year),
+            ..Self::default()
+        }
     }
 
-    if let Ok(dt) = NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S%.f") {
-        return Some(dt.timestamp_millis());
+    /// Times without a UTC offset are considered local to the timezone.
+    pub fn parse(&self, s: &str, tz: &Timezone) -> Option<Timestamp> {
+        let s = s.trim().trim_start_matches('[').trim_end_matches(']');
+
+        let last = self.last.load(Ordering::Relaxed);
+        if let Some(ts) = TIME_FORMATS[last].parse(s, tz, self.year) {
+            return Some(ts);
+        }
+
+        for (idx, format) in TIME_FORMATS.iter().enumerate() {
+            if idx == last {
+                continue;
+            }
+            if let Some(ts) = format.parse(s, tz, self.year) {
+                self.last.store(idx, Ordering::Relaxed);
+                return Some(ts);
+            }
+        }
+        None
     }
+}
+
+/// Guesses the format of a timestamp. Times without a UTC offset are
+/// considered local to the timezone.
+pub fn try_parse_time(s: &str, tz: &Timezone) -> Option<Timestamp> {
+    TimeFormatGuesser::default().parse(s, tz)
+}
 
-    if let Ok(dt) = DateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S%.f %z") {
-        return Some(dt.timestamp_millis());
+fn try_parse_naive_time(s: &str) -> Option<NaiveDateTime> {
+    TIME_FORMATS.iter().find_map(|format| match format {
+        TimeFormat::Naive(format) => NaiveDateTime::parse_from_str(s, format).ok(),
+        _ => None,
+    })
+}
+
+// The placeholder year is a leap one to keep Feb 29.
+fn try_parse_no_year_time(s: &str) -> Option<NaiveDateTime> {
+    TIME_FORMATS.iter().find_map(|format| match format {
+        TimeFormat::NoYear(format) => {
+            NaiveDateTime::parse_from_str(&format!("2000 {}", s), &format!("%Y {}", format)).ok()
+        }
+        _ => None,
+    })
+}
+
+/// A time lacking the year is in the given year or, by default, in the
+/// current one. Times from late December read in early January belong
+/// to the past year though.
+fn resolve_no_year(
+    in_year: impl Fn(i32) -> Option<Timestamp>,
+    year: Option<i32>,
+    now: DateTime<Utc>,
+) -> Option<Timestamp> {
+    if let Some(year) = year {
+        return in_year(year);
+    }
+    match in_year(now.year())? {
+        ts if ts > now.timestamp_millis() + 24 * 60 * 60 * 1000 => in_year(now.year() - 1),
+        ts => Some(ts),
     }
+}
 
-    // UNIX timestamp
-    if s.chars().all(|c| char::is_digit(c, 10)) {
-        let n = s.parse::<i64>().unwrap();
-        match s.len() {
-            10 => return Some(n * 1000),
-            13 => return Some(n),
-            _ => return None,
-        };
+// UNIX timestamp in seconds (optionally fractional), milli-, micro-, or nanoseconds.
+fn try_parse_epoch(s: &str) -> Option<Timestamp> {
+    let (int, frac) = match s.split_once('.') {
+        Some((int, frac)) => (int, Some(frac)),
+        None => (s, None),
+    };
+
+    let is_digits = |s: &str| !s.is_empty() && s.bytes().all(|c| c.is_ascii_digit());
+    if !is_digits(int) || !frac.is_none_or(is_digits) {
+        return None;
     }
 
-    None
+    let n = int.parse::<i64>().ok()?;
+    match (int.len(), frac) {
+        (10, None) => Some(n * 1000),
+        (10, Some(frac)) => {
+            let millis = format!("{:0<3}", &frac[..frac.len().min(3)]);
+            Some(n * 1000 + millis.parse::<i64>().ok()?)
+        }
+        (13, _) => Some(n),
+        (16, _) => Some(n / 1_000),
+        (19, _) => Some(n / 1_000_000),
+        _ => None,
+    }
 }
 
 #[cfg(test)]
@@ -106,14 +453,163 @@ mod tests {
             ("01/Jan/2021:00:00:00 -0000", 1609459200000),
             ("1609459200",    1609459200000),
             ("1609459200100", 1609459200100),
+            ("1609459200.1",  1609459200100),
+            ("1609459200.123456", 1609459200123),
+            ("1609459200123456", 1609459200123),
+            ("1609459200123456789", 1609459200123),
+            ("2021-06-01 12:00:00,123", 1622548800123),
+            ("2021/06/01 12:00:00", 1622548800000),
+            ("2021/06/01 12:00:00.123456", 1622548800123),
+            ("Tue Jun 01 12:00:00.123456 2021", 1622548800123),
+            ("2021-W22-2T12:00:00", 1622548800000),
+            ("2021-W22-2 12:00:00.123", 1622548800123),
+            ("[01/Jun/2021:14:00:00 +0200]", 1622548800000),
         ];
 
         for (input, expected) in &tests {
-            let actual =
-                try_parse_time(input).unwrap_or_else(|| panic!("failed to parse {}", input));
+            let actual = try_parse_time(input, &Timezone::default())
+                .unwrap_or_else(|| panic!("failed to parse {}", input));
             assert_eq!(*expected, actual);
         }
 
+        let new_year = Utc
+            .ymd(Utc::now().year(), 1, 1)
+            .and_hms(0, 0, 0)
+            .timestamp_millis();
+        assert_eq!(
+            Some(new_year),
+            try_parse_time("Jan  1 00:00:00", &Timezone::default())
+        );
+        assert_eq!(
+            Some(new_year + 500),
+            try_parse_time("Jan 01 00:00:00.5", &Timezone::default())
+        );
+        assert_eq!(
+            Some(1640995199000),
+            TimeFormatGuesser::with_year(2021).parse("Dec 31 23:59:59", &Timezone::default())
+        );
+
+        for input in &["16094592001", "1609459200.", ".5", "1609459200.1e3"] {
+            assert_eq!(
+                None,
+                try_parse_time(input, &Timezone::default()),
+                "while parsing {}",
+                input
+            );
+        }
+
+        #[rustfmt::skip]
+        let tests = [
+            ("2021-01-01 01:00:00", "Europe/Berlin", 1609459200000),
+            ("2021-07-01 02:00:00", "Europe/Berlin", 1625097600000),
+            ("2021-07-01T02:00:00", "+02:00", 1625097600000),
+            // Timezones don't affect timestamps with explicit offsets.
+            ("2021-01-01T00:00:00Z", "Europe/Berlin", 1609459200000),
+            ("1609459200", "America/New_York", 1609459200000),
+            // DST fall back: the earlier instant wins.
+            ("2021-10-31 02:30:00", "Europe/Berlin", 1635640200000),
+            // DST spring forward: 02:30 doesn't exist, it's 03:30 CEST.
+            ("2021-03-28 02:30:00", "Europe/Berlin", 1616895000000),
+        ];
+
+        for (input, tz, expected) in &tests {
+            let actual = try_parse_time(input, &parse_timezone(tz)?)
+                .unwrap_or_else(|| panic!("failed to parse {}", input));
+            assert_eq!(*expected, actual, "while parsing {} in {}", input, tz);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_time_format_guesser() {
+        let guesser = TimeFormatGuesser::default();
+        let tz = Timezone::default();
+
+        assert_eq!(
+            Some(1622548800000),
+            guesser.parse("2021/06/01 12:00:00", &tz)
+        );
+        let cached = guesser.last.load(Ordering::Relaxed);
+        assert!(matches!(
+            TIME_FORMATS[cached],
+            TimeFormat::Naive("%Y/%m/%d %H:%M:%S%.f")
+        ));
+
+        assert_eq!(
+            Some(1622548801000),
+            guesser.parse("2021/06/01 12:00:01", &tz)
+        );
+        assert_eq!(cached, guesser.last.load(Ordering::Relaxed));
+
+        // Still falls back to the other formats.
+        assert_eq!(Some(1622548800000), guesser.parse("1622548800", &tz));
+        assert!(matches!(
+            TIME_FORMATS[guesser.last.load(Ordering::Relaxed)],
+            TimeFormat::Epoch
+        ));
+        assert_eq!(None, guesser.parse("foobar", &tz));
+    }
+
+    #[test]
+    fn test_time_expr() -> std::result::Result<(), Box<dyn std::error::Error>> {
+        // 2021-01-02T03:04:05Z
+        let now = Utc.timestamp(1609556645, 0);
+
+        #[rustfmt::skip]
+        let tests = [
+            ("2021-01-01 00:00:00", "UTC", 1609459200000),
+            ("1609459200", "+02:00", 1609459200000),
+            ("now", "UTC", 1609556645000),
+            ("now-30m", "UTC", 1609556645000 - 30 * 60 * 1000),
+            ("now - 1h", "UTC", 1609556645000 - 60 * 60 * 1000),
+            ("now+1d", "UTC", 1609556645000 + 24 * 60 * 60 * 1000),
+            ("2h", "UTC", 1609556645000 - 2 * 60 * 60 * 1000),
+            ("today", "UTC", 1609545600000),
+            ("today 09:00", "UTC", 1609545600000 + 9 * 60 * 60 * 1000),
+            ("yesterday", "UTC", 1609459200000),
+            ("yesterday 23:59:59", "UTC", 1609545599000),
+            ("today", "+05:00", 1609545600000 - 5 * 60 * 60 * 1000),
+            ("today", "-0400", 1609459200000 + 4 * 60 * 60 * 1000),
+            ("today", "Asia/Tokyo", 1609545600000 - 9 * 60 * 60 * 1000),
+            ("yesterday 12:00", "America/New_York", 1609459200000 - 7 * 60 * 60 * 1000),
+            ("2021-01-01 00:00:00", "Europe/Berlin", 1609459200000 - 60 * 60 * 1000),
+        ];
+
+        for (input, tz, expected) in &tests {
+            let actual = input
+                .parse::<TimeExpr>()
+                .map_err(|e| format!("failed to parse {}: {}", input, e))?
+                .resolve(now, &parse_timezone(tz)?, None);
+            assert_eq!(*expected, actual, "while resolving {} in {}", input, tz);
+        }
+
+        // --since "Jun  1 12:00:00" --tz Europe/Berlin [--year 2020]
+        let since = "Jun  1 12:00:00".parse::<TimeExpr>()?;
+        let tz = parse_timezone("Europe/Berlin")?;
+        assert_eq!(1622541600000, since.resolve(now, &tz, Some(2021)));
+        assert_eq!(1591005600000, since.resolve(now, &tz, Some(2020)));
+        // Not in the future (now is early January).
+        assert_eq!(1591005600000, since.resolve(now, &tz, None));
+        // Feb 29 of a non-leap year is Mar 1.
+        let leap_day = "Feb 29 12:00:00".parse::<TimeExpr>()?;
+        assert_eq!(
+            1614600000000,
+            leap_day.resolve(now, &Timezone::default(), Some(2021))
+        );
+
+        for input in &["now*2", "tomorrow", "today 25:00", "2x"] {
+            assert!(
+                input.parse::<TimeExpr>().is_err(),
+                "while parsing {}",
+                input
+            );
+        }
+
+        for input in &["Mars/Olympus", "+25:00", "0200"] {
+            assert!(parse_timezone(input).is_err(), "while parsing {}", input);
+        }
+
         Ok(())
     }
 }
@@ -7,10 +7,9 @@ use std::rc::Rc;
 use structopt::StructOpt;
 
 use pq::cliopt::CliOpt;
-use pq::input::LineReader;
+use pq::input::{LineReader, OffsetTracker, Progress};
 use pq::output::{LineWriter, Writer};
-use pq::runner::{Runner, RunnerOptions};
-use pq::utils::time::TimeRange;
+use pq::runner::Runner;
 
 #[test]
 fn e2e() -> Result<(), Box<dyn std::error::Error>> {
@@ -73,8 +72,58 @@ fn e2e() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+#[test]
+fn checkpoint() -> Result<(), Box<dyn std::error::Error>> {
+    let input: String = (0..5000)
+        .map(|i| {
+            format!(
+                "2021-01-01T{:02}:{:02}:{:02}Z foo\n",
+                i / 3600,
+                i / 60 % 60,
+                i % 60
+            )
+        })
+        .collect();
+    let line_len = input.find('\n').unwrap() as u64 + 1;
+
+    for jobs in &["1", "4"] {
+        let cli_opt = CliOpt::from_iter_safe(&[
+            "pq",
+            "--jobs",
+            jobs,
+            "--until",
+            "2021-01-01T00:49:59Z",
+            r#"/([^ ]+) (\w+)/ | map {.0:ts, .1 as v}"#,
+        ])?;
+
+        let progress = Progress::default();
+        let tracker = OffsetTracker::new(
+            LineReader::new(io::Cursor::new(input.clone().into_bytes())),
+            0,
+            progress.clone(),
+        );
+        let offset = tracker.offset();
+
+        let options = cli_opt
+            .runner_options(cli_opt.time_range()?)
+            .with_progress(progress);
+        let mut runner = Runner::new(
+            &cli_opt.program,
+            Box::new(tracker),
+            Box::new(LineWriter::new(io::sink())),
+            options,
+        )?;
+        runner.run()?;
+        drop(runner);
+
+        // Lines read ahead by the workers or past --until must be read again.
+        assert_eq!(3000 * line_len, offset.get(), "--jobs {}", jobs);
+    }
+    Ok(())
+}
+
 fn query(
-    input_reader: Box<dyn io::BufRead>,
+    input_reader: Box<dyn io::BufRead + Send>,
     cli_args: &[String],
 ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
     let writer = Rc::new(RefCell::new(LineWriter::new(
@@ -95,13 +144,7 @@ fn query(
         &cli_opt.program,
         Box::new(LineReader::new(input_reader)),
         Box::new(MockWriter(Rc::clone(&writer))),
-        RunnerOptions::new(
-            cli_opt.verbose,
-            cli_opt.interactive,
-            Some(TimeRange::new(cli_opt.since, cli_opt.until)?),
-            cli_opt.interval,
-            cli_opt.lookback,
-        ),
+        cli_opt.runner_options(cli_opt.time_range()?),
     )?;
     runner.run()?;
 